@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use build_time::{build_time_local, build_time_utc};
 use git_const::git_hash;
 use itertools::Itertools;
@@ -8,11 +10,16 @@ use teloxide::{
 
 use crate::{
     bot_manager::DEFAULT_SCRIPT,
+    broadcast::{is_blocked, Broadcast, BroadcastProgress},
     db::{bots::BotInstance, CallDB, DB},
     BotResult,
 };
 use crate::{BotDialogue, LogMsg, State};
-use log::info;
+use log::{error, info};
+use tokio::sync::watch;
+
+/// how often status message of broadcast is updated
+const BROADCAST_PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
 
 // These are should not appear in /help
 #[derive(BotCommands, Clone)]
@@ -38,10 +45,22 @@ pub enum AdminCommands {
     /// Set specified literal value
     #[command(description = "handle a username and an age.", parse_with = "split")]
     SetAlternative { literal: String, variant: String },
+    /// Set literal value for users with specified language, e.g. `/setliterallang start en`
+    #[command(parse_with = "split")]
+    SetLiteralLang { literal: String, lang: String },
+    /// Set literal alternative value for users with specified language
+    #[command(parse_with = "split")]
+    SetAlternativeLang {
+        literal: String,
+        variant: String,
+        lang: String,
+    },
     /// Sets chat where this message entered as support's chats
     SetChat,
     /// Shows user count and lists some of them
     Users,
+    /// Reply with it to message, that should be sent to all users
+    Broadcast,
     /// Cancel current action and sets user state to default
     Cancel,
     /// Create new instance of telegram bot
@@ -102,7 +121,7 @@ pub async fn admin_command_handler(
                 .update(State::Edit {
                     literal,
                     variant: None,
-                    lang: "ru".to_string(),
+                    lang: None,
                     is_caption_set: false,
                 })
                 .await?;
@@ -111,12 +130,26 @@ pub async fn admin_command_handler(
 
             Ok(())
         }
+        AdminCommands::SetLiteralLang { literal, lang } => {
+            dialogue
+                .update(State::Edit {
+                    literal,
+                    variant: None,
+                    lang: Some(lang.clone()),
+                    is_caption_set: false,
+                })
+                .await?;
+            bot.send_message(msg.chat.id, format!("Send message for literal in `{lang}`"))
+                .await?;
+
+            Ok(())
+        }
         AdminCommands::SetAlternative { literal, variant } => {
             dialogue
                 .update(State::Edit {
                     literal,
                     variant: Some(variant),
-                    lang: "ru".to_string(),
+                    lang: None,
                     is_caption_set: false,
                 })
                 .await?;
@@ -125,6 +158,27 @@ pub async fn admin_command_handler(
 
             Ok(())
         }
+        AdminCommands::SetAlternativeLang {
+            literal,
+            variant,
+            lang,
+        } => {
+            dialogue
+                .update(State::Edit {
+                    literal,
+                    variant: Some(variant),
+                    lang: Some(lang.clone()),
+                    is_caption_set: false,
+                })
+                .await?;
+            bot.send_message(
+                msg.chat.id,
+                format!("Send message for literal alternative in `{lang}`"),
+            )
+            .await?;
+
+            Ok(())
+        }
         AdminCommands::SetChat => {
             dialogue.exit().await?;
             db.set_literal("support_chat_id", &msg.chat.id.0.to_string())
@@ -157,6 +211,35 @@ pub async fn admin_command_handler(
 
             Ok(())
         }
+        AdminCommands::Broadcast => {
+            let message = match msg.reply_to_message() {
+                Some(message) => message.clone(),
+                None => {
+                    bot.send_message(
+                        msg.chat.id,
+                        "you need to reply to some message with this command",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+            let users = db.get_active_users().await?;
+            let status = bot
+                .send_message(
+                    msg.chat.id,
+                    format!("Broadcast started, users: {}", users.len()),
+                )
+                .await?;
+
+            let users = users.into_iter().map(|user| user.id).collect();
+            let db = db.clone();
+            tokio::spawn(async move {
+                if let Err(err) = broadcast_message(bot, db, users, message, status).await {
+                    error!("Broadcast failed, err: {err}");
+                }
+            });
+            Ok(())
+        }
         AdminCommands::Cancel => {
             dialogue.exit().await?;
             bot.send_message(msg.chat.id, "canceled current action")
@@ -206,6 +289,72 @@ pub async fn admin_command_handler(
     }
 }
 
+/// copies message to all users, updating status message with progress,
+/// users, that have blocked bot, are marked as blocked
+async fn broadcast_message(
+    bot: Bot,
+    mut db: DB,
+    users: Vec<i64>,
+    message: Message,
+    status: Message,
+) -> BotResult<()> {
+    let (progress_tx, mut progress_rx) = watch::channel(BroadcastProgress::default());
+    let reporter = {
+        let (bot, status) = (bot.clone(), status.clone());
+        tokio::spawn(async move {
+            while progress_rx.changed().await.is_ok() {
+                let progress = *progress_rx.borrow_and_update();
+                let text = format!("Broadcast in progress, {progress}");
+                // progress is not important enough to stop broadcast
+                let _ = bot.edit_message_text(status.chat.id, status.id, text).await;
+                tokio::time::sleep(BROADCAST_PROGRESS_INTERVAL).await;
+            }
+        })
+    };
+
+    let items = users.into_iter().map(|id| (id, id)).collect();
+    let results = Broadcast::new(&bot)
+        .run(
+            items,
+            |chat_id, _| {
+                let (bot, message) = (&bot, &message);
+                async move {
+                    bot.copy_message(ChatId(chat_id), message.chat.id, message.id)
+                        .await?;
+                    Ok(())
+                }
+            },
+            |progress| {
+                progress_tx.send_replace(*progress);
+            },
+        )
+        .await;
+    drop(progress_tx);
+    let _ = reporter.await;
+
+    let sent = results.iter().filter(|(_, result)| result.is_ok()).count();
+    let mut blocked = 0;
+    for (user_id, result) in &results {
+        if let Err(err) = result {
+            if is_blocked(err) {
+                db.set_user_blocked(*user_id, true).await?;
+                blocked += 1;
+            }
+        }
+    }
+    bot.edit_message_text(
+        status.chat.id,
+        status.id,
+        format!(
+            "Broadcast finished, sent: {sent}, failed: {}, blocked: {blocked}",
+            results.len() - sent
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
 pub async fn secret_command_handler(
     mut db: DB,
     //config: Config,
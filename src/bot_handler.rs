@@ -1,32 +1,38 @@
-use futures::future::join_all;
-use log::{error, info};
-use quickjs_rusty::serde::to_js;
+use log::error;
 use serde_json::Value;
 use std::{
+    collections::HashMap,
     str::FromStr,
     sync::{Arc, Mutex},
 };
 use teloxide::{
-    dispatching::{dialogue::GetChatId, UpdateFilterExt},
+    dispatching::{
+        dialogue::{serializer::Json, GetChatId},
+        HandlerExt, UpdateFilterExt,
+    },
     dptree::{self, Handler},
     prelude::{DependencyMap, Requester},
-    types::{CallbackQuery, InlineKeyboardMarkup, Message, Update},
+    types::{CallbackQuery, ChatId, Message, Update, User},
     Bot,
 };
 
 use crate::{
-    botscript::{self, message_info::MessageInfoBuilder, ScriptError},
+    botscript::message_info::{MessageInfo, MessageInfoBuilder},
     commands::BotCommand,
     config::{
-        dialog::{button::ButtonLayout, message::BotMessage},
-        traits::ProviderSerialize,
-        Provider,
+        dialog::message::{BotMessage, HandlerReturn, Toast},
+        function::BotFunction,
+        notification::relative::{RelativeNotifications, UserEvent},
+        result::ConfigError,
+        traits::{ProviderDeserialize, ProviderSerialize},
+        ExpiredCallback, Provider,
     },
-    db::{callback_info::CallbackInfo, CallDB, DB},
+    db::{callback_info::CallbackInfo, CallDB, Langs, DB},
     message_answerer::MessageAnswerer,
+    mongodb_storage::MongodbStorage,
     notify_admin, update_user_tg,
-    utils::callback_button,
-    BotError, BotResult, BotRuntime,
+    utils::{reply_markup, template::TemplateContext},
+    BotDialogue, BotResult, BotRuntime, State,
 };
 
 pub type BotHandler =
@@ -34,9 +40,41 @@ pub type BotHandler =
 
 type CallbackStore = CallbackInfo<Value>;
 
-pub fn script_handler<P: Provider + Send + Sync>(r: Arc<Mutex<BotRuntime<P>>>) -> BotHandler {
+/// state, that resets user's script state
+const NONE_STATE: &str = "none";
+
+/// language of literals set in bot's config, used if user's one is not set
+#[derive(Clone)]
+struct DefaultLanguage(Option<String>);
+
+impl DefaultLanguage {
+    fn langs(&self, tguser: &User) -> Langs {
+        Langs::new(tguser.language_code.as_deref(), self.0.as_deref())
+    }
+}
+
+/// answer to click on button, which callback has expired or is unknown
+#[derive(Clone)]
+enum ExpiredAnswer<P: Provider> {
+    /// literal of popup's text
+    Alert(String),
+    Message(BotMessage<P>),
+}
+
+pub fn script_handler<P: Provider>(r: Arc<Mutex<BotRuntime<P>>>) -> BotHandler {
     let cr = r.clone();
+    let sr = r.clone();
+    let er = r.clone();
+    let (default_lang, relative) = {
+        let r = r.lock().expect("RwLock lock on commands map failed");
+        (
+            DefaultLanguage(r.rc.default_language().map(str::to_string)),
+            RelativeNotifications::from(r.rc.relative_notifications()),
+        )
+    };
     dptree::entry()
+        .map(move || default_lang.clone())
+        .map(move || relative.clone())
         .branch(
             Update::filter_message()
                 // check if message is command
@@ -55,7 +93,7 @@ pub fn script_handler<P: Provider + Send + Sync>(r: Arc<Mutex<BotRuntime<P>>>) -
                         None => rc.get_command_message(command),
                     }
                 })
-                .endpoint(handle_botmessage),
+                .endpoint(handle_botmessage::<P>),
         )
         .branch(
             Update::filter_callback_query()
@@ -83,17 +121,220 @@ pub fn script_handler<P: Provider + Send + Sync>(r: Arc<Mutex<BotRuntime<P>>>) -
                         };
 
                         let data = match ci.literal {
-                            Some(data) => data,
+                            Some(ref data) => data,
                             None => return None,
                         };
 
                         let r = r.lock().expect("RwLock lock on commands map failed");
                         let rc = &r.rc;
-                        rc.get_callback_message(&data)
+                        rc.get_callback_message(data).map(|bm| (bm, ci))
                     }
                 })
-                .endpoint(handle_callback),
+                .endpoint(handle_callback::<P>),
         )
+        .branch(
+            // callback is not handled by previous branch, so it is unknown
+            Update::filter_callback_query()
+                .filter_map(move || {
+                    let r = er.lock().expect("RwLock lock on commands map failed");
+                    match r.rc.expired_callback() {
+                        ExpiredCallback::Ignore => None,
+                        ExpiredCallback::Alert(literal) => {
+                            Some(ExpiredAnswer::Alert(literal.clone()))
+                        }
+                        ExpiredCallback::Start => {
+                            r.rc.get_command_message("start")
+                                .map(ExpiredAnswer::Message)
+                        }
+                    }
+                })
+                .endpoint(handle_expired_callback::<P>),
+        )
+        .branch(
+            Update::filter_message()
+                .enter_dialogue::<Message, MongodbStorage<Json>, State>()
+                // check if user is in script's state, that has handler
+                .filter_map(move |state: State| {
+                    let state = match state {
+                        State::Script { state } => state,
+                        _ => return None,
+                    };
+
+                    let r = sr.lock().expect("RwLock lock on commands map failed");
+                    r.rc.get_stateful_message(&state)
+                })
+                .endpoint(handle_stateful::<P>),
+        )
+}
+
+/// data of button, stored with callback, if it is set
+fn callback_data(callback: Value) -> Option<Value> {
+    match callback.get("data") {
+        None | Some(Value::Null) => None,
+        Some(data) => Some(data.clone()),
+    }
+}
+
+/// message, that should be sent after handler
+struct Answer<P: Provider> {
+    bm: BotMessage<P>,
+    variant: Option<String>,
+    /// text set by handler, sent instead of literal
+    text: Option<String>,
+    /// variables set by handler for message's placeholders
+    vars: HashMap<String, Value>,
+    is_propagate: bool,
+}
+
+impl<P: Provider> Answer<P> {
+    fn literal(&self) -> &str {
+        self.bm.literal().map_or("", |s| s.as_str())
+    }
+
+    fn context(&self, tguser: &User) -> TemplateContext {
+        TemplateContext::default()
+            .with_user(tguser)
+            .with_vars(self.vars.clone())
+    }
+}
+
+/// calls message's handler if it is set and applies its return to message
+async fn run_handler<P: Provider>(
+    bm: BotMessage<P>,
+    variant: Option<String>,
+    tguser: &User,
+    mi: &MessageInfo,
+) -> BotResult<Answer<P>> {
+    let ret = match bm.get_handler() {
+        Some(handler) => call_handler(handler, tguser, mi).await?,
+        None => HandlerReturn::Propagate(true),
+    };
+
+    let answer = match ret {
+        HandlerReturn::Message(o) => Answer {
+            bm: bm.update_with(&o),
+            variant: o.variant.or(variant),
+            text: o.text,
+            vars: o.vars.unwrap_or_default(),
+            is_propagate: true,
+        },
+        ret => Answer {
+            is_propagate: ret.is_propagate(),
+            bm,
+            variant,
+            text: None,
+            vars: HashMap::new(),
+        },
+    };
+
+    Ok(answer)
+}
+
+async fn call_handler<P: Provider>(
+    handler: &BotFunction<P>,
+    tguser: &User,
+    mi: &MessageInfo,
+) -> BotResult<HandlerReturn<P>> {
+    let puser =
+        <P::Value as ProviderSerialize>::se_from(tguser).map_err(ConfigError::as_provider_err)?;
+    let pmi = <P::Value as ProviderSerialize>::se_from(mi).map_err(ConfigError::as_provider_err)?;
+    let ret = match handler.call_args_async(&[&puser, &pmi]).await {
+        Ok(Some(v)) => match v.de_into() {
+            Ok(ret) => ret,
+            Err(err) => {
+                error!("Handler returned unsupported value, err: {err}");
+                // falling back to propagation
+                HandlerReturn::Propagate(true)
+            }
+        },
+        // nothing returned, falling back to propagation
+        Ok(None) => HandlerReturn::Propagate(true),
+        Err(err) => {
+            error!("Failed to get return of handler, err: {err}");
+            // falling back to propagation
+            HandlerReturn::Propagate(true)
+        }
+    };
+
+    Ok(ret)
+}
+
+/// sends answer as a new message
+async fn send_answer<P: Provider>(
+    bot: &Bot,
+    db: &mut DB,
+    chat_id: i64,
+    tguser: &User,
+    langs: Langs,
+    answer: &Answer<P>,
+) -> BotResult<()> {
+    let layout = answer.bm.resolve_buttons(db, &langs).await?;
+    let buttons = reply_markup(layout, answer.bm.keyboard_type(), db).await?;
+
+    let ma = MessageAnswerer::new(bot, db, chat_id)
+        .with_context(answer.context(tguser))
+        .with_langs(langs);
+    match &answer.text {
+        Some(text) => ma.answer_text(text.clone(), buttons).await?,
+        None => {
+            ma.answer(answer.literal(), answer.variant.as_deref(), buttons)
+                .await?
+        }
+    };
+
+    Ok(())
+}
+
+/// sets user's script state to the state of sent message, if it is set
+async fn update_script_state<P: Provider>(
+    state_mgr: Arc<MongodbStorage<Json>>,
+    chat_id: i64,
+    bm: &BotMessage<P>,
+) -> BotResult<()> {
+    let state = match bm.state() {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+
+    let dialogue = BotDialogue::new(state_mgr, ChatId(chat_id));
+    match state.as_str() {
+        NONE_STATE => dialogue.exit().await?,
+        state => {
+            dialogue
+                .update(State::Script {
+                    state: state.to_string(),
+                })
+                .await?
+        }
+    };
+
+    Ok(())
+}
+
+/// gets user from db, updating it with telegram's info, and starts timers of
+/// notifications after user's first interaction
+async fn init_user(
+    db: &mut DB,
+    tguser: &User,
+    relative: &RelativeNotifications,
+) -> BotResult<crate::db::User> {
+    let user = db
+        .get_or_init_user(tguser.id.0 as i64, &tguser.first_name)
+        .await?;
+    let user = update_user_tg(user, tguser);
+    user.update_user(db).await?;
+
+    // user can be created by other handlers, so timers are started once by time of creation,
+    // and only until they expire
+    if !user.first_seen_scheduled && user.mark_first_seen_scheduled(db).await? {
+        if let Some(created_at) = user.created_at {
+            relative
+                .schedule_from(db, user.id, &UserEvent::FirstSeen, created_at.to_chrono())
+                .await?;
+        }
+    }
+
+    Ok(user)
 }
 
 async fn handle_botmessage<P: Provider>(
@@ -101,112 +342,68 @@ async fn handle_botmessage<P: Provider>(
     mut db: DB,
     bm: BotMessage<P>,
     msg: Message,
+    state_mgr: Arc<MongodbStorage<Json>>,
+    default_lang: DefaultLanguage,
+    relative: RelativeNotifications,
 ) -> BotResult<()> {
     // info!("Eval BM: {:?}", bm);
     let tguser = match msg.from.clone() {
         Some(user) => user,
         None => return Ok(()), // do nothing, cause its not usecase of function
     };
-    let user = db
-        .get_or_init_user(tguser.id.0 as i64, &tguser.first_name)
-        .await?;
-    let user = update_user_tg(user, &tguser);
-    user.update_user(&mut db).await?;
+    let user = init_user(&mut db, &tguser, &relative).await?;
 
-    let variant = match BotCommand::from_str(msg.text().unwrap_or("")) {
-        Ok(cmd) => cmd.args().map(|m| m.to_string()),
-        Err(_) => None,
-    };
+    let command = BotCommand::from_str(msg.text().unwrap_or("")).ok();
+    let variant = command
+        .as_ref()
+        .and_then(|cmd| cmd.args().map(|m| m.to_string()));
+    let args = command.as_ref().map_or(vec![], |cmd| {
+        cmd.args_list().into_iter().map(str::to_string).collect()
+    });
 
     if bm.meta() {
         if let Some(ref meta) = variant {
             user.insert_meta(&mut db, meta).await?;
+            relative
+                .schedule(&mut db, user.id, &UserEvent::Meta(meta.clone()))
+                .await?;
         };
     };
 
-    let is_propagate: bool = match bm.get_handler() {
-        Some(handler) => 'prop: {
-            // let ctx = match handler.context() {
-            //     Some(ctx) => ctx,
-            //     // falling back to propagation
-            //     None => break 'prop true,
-            // };
-            // let jsuser = to_js(ctx, &tguser).map_err(ScriptError::from)?;
-            let puser = <P::Value as ProviderSerialize>::se_from(&tguser).unwrap();
-            let mi = MessageInfoBuilder::new()
-                .set_variant(variant.clone())
-                .build();
-            // let mi = to_js(ctx, &mi).map_err(ScriptError::from)?;
-            let pmi = <P::Value as ProviderSerialize>::se_from(&mi).unwrap();
-            // info!(
-            //     "Calling handler {:?} with msg literal: {:?}",
-            //     handler,
-            //     bm.literal()
-            // );
-            match handler.call_args(vec![puser, pmi]) {
-                Ok(v) => {
-                    todo!()
-                    // if v.is_bool() {
-                    //     v.to_bool().unwrap_or(true)
-                    // } else if v.is_int() {
-                    //     v.to_int().unwrap_or(1) != 0
-                    // } else {
-                    //     // falling back to propagation
-                    //     true
-                    // }
-                }
-                Err(err) => {
-                    error!("Failed to get return of handler, err: {err}");
-                    // falling back to propagation
-                    true
-                }
-            }
-        }
-        None => true,
-    };
+    let mi = MessageInfoBuilder::new()
+        .set_message(&msg)
+        .set_variant(variant.clone())
+        .set_args(args)
+        .set_metas(user.metas.clone())
+        .build();
+    let answer = run_handler(bm, variant, &tguser, &mi).await?;
+    update_script_state(state_mgr, msg.chat.id.0, &answer.bm).await?;
 
-    if !is_propagate {
+    if !answer.is_propagate {
         return Ok(());
     }
 
-    let button_db = db.clone();
-    let buttons = bm.resolve_buttons(&mut db).await?.map(async |buttons| {
-        join_all(buttons.iter().map(async |r| {
-            join_all(r.iter().map(async |b| {
-                match b {
-                    ButtonLayout::Callback {
-                        name,
-                        literal: _,
-                        callback,
-                    } => {
-                        callback_button(
-                            name,
-                            callback.to_string(),
-                            None::<bool>,
-                            &mut button_db.clone(),
-                        )
-                        .await
-                    }
-                }
-            }))
-            .await
-            .into_iter()
-            .collect::<Result<_, _>>()
-        }))
-        .await
-        .into_iter()
-        .collect::<Result<_, _>>()
-    });
-    let buttons = match buttons {
-        Some(b) => Some(InlineKeyboardMarkup {
-            inline_keyboard: b.await?,
-        }),
+    let langs = default_lang.langs(&tguser);
+    send_answer(&bot, &mut db, msg.chat.id.0, &tguser, langs, &answer).await
+}
+
+/// answers callback query, showing toast if it is set
+async fn answer_callback_query(
+    bot: &Bot,
+    db: &DB,
+    q: &CallbackQuery,
+    toast: Option<&Toast>,
+    langs: &Langs,
+) -> BotResult<()> {
+    let query = bot.answer_callback_query(&q.id);
+    let toast = match toast {
+        Some(toast) => toast.resolve(db, langs).await?.map(|text| (text, toast)),
         None => None,
     };
-    let literal = bm.literal().map_or("", |s| s.as_str());
-
-    let ma = MessageAnswerer::new(&bot, &mut db, msg.chat.id.0);
-    ma.answer(literal, variant.as_deref(), buttons).await?;
+    match toast {
+        Some((text, toast)) => query.text(text).show_alert(toast.show_alert).await?,
+        None => query.await?,
+    };
 
     Ok(())
 }
@@ -214,119 +411,137 @@ async fn handle_botmessage<P: Provider>(
 async fn handle_callback<P: Provider>(
     bot: Bot,
     mut db: DB,
-    bm: BotMessage<P>,
+    (bm, ci): (BotMessage<P>, CallbackStore),
     q: CallbackQuery,
+    state_mgr: Arc<MongodbStorage<Json>>,
+    default_lang: DefaultLanguage,
+    relative: RelativeNotifications,
 ) -> BotResult<()> {
-    bot.answer_callback_query(&q.id).await?;
     // info!("Eval BM: {:?}", bm);
     let tguser = q.from.clone();
-    let user = db
-        .get_or_init_user(tguser.id.0 as i64, &tguser.first_name)
-        .await?;
-    let user = update_user_tg(user, &tguser);
-    user.update_user(&mut db).await?;
-
-    let is_propagate: bool = match bm.get_handler() {
-        Some(handler) => 'prop: {
-            let puser = <P::Value as ProviderSerialize>::se_from(&tguser).unwrap();
-            let mi = MessageInfoBuilder::new().build();
-            let pmi = <P::Value as ProviderSerialize>::se_from(&mi).unwrap();
-            match handler.call_args(vec![puser, pmi]) {
-                Ok(v) => {
-                    todo!()
-                    // if v.is_bool() {
-                    //     v.to_bool().unwrap_or(true)
-                    // } else if v.is_int() {
-                    //     v.to_int().unwrap_or(1) != 0
-                    // } else {
-                    //     // falling back to propagation
-                    //     true
-                    // }
-                }
-                Err(err) => {
-                    error!("Failed to get return of handler, err: {err}");
-                    // falling back to propagation
-                    true
-                }
-            }
-        }
-        None => true,
-    };
+    let user = init_user(&mut db, &tguser, &relative).await?;
 
-    if !is_propagate {
-        return Ok(());
+    if let Some(ref literal) = ci.literal {
+        let event = UserEvent::Callback(literal.clone());
+        relative.schedule(&mut db, user.id, &event).await?;
     }
 
-    let button_db = db.clone();
-    let buttons = bm.resolve_buttons(&mut db).await?.map(async |buttons| {
-        join_all(buttons.iter().map(async |r| {
-            join_all(r.iter().map(async |b| {
-                match b {
-                    ButtonLayout::Callback {
-                        name,
-                        literal: _,
-                        callback,
-                    } => {
-                        callback_button(
-                            name,
-                            callback.to_string(),
-                            None::<bool>,
-                            &mut button_db.clone(),
-                        )
-                        .await
-                    }
-                }
-            }))
-            .await
-            .into_iter()
-            .collect::<Result<_, _>>()
-        }))
-        .await
-        .into_iter()
-        .collect::<Result<_, _>>()
-    });
-    let buttons = match buttons {
-        Some(b) => Some(InlineKeyboardMarkup {
-            inline_keyboard: b.await?,
-        }),
-        None => None,
+    let mi = match q.regular_message() {
+        Some(msg) => MessageInfoBuilder::new().set_message(msg),
+        None => MessageInfoBuilder::new(),
     };
-    let literal = bm.literal().map_or("", |s| s.as_str());
+    let mi = mi
+        .set_callback(ci.literal.unwrap_or_default(), callback_data(ci.callback))
+        .set_metas(user.metas.clone())
+        .build();
+    let answer = run_handler(bm, None, &tguser, &mi).await?;
+    let langs = default_lang.langs(&tguser);
+    // answered after handler, since it can set toast
+    answer_callback_query(&bot, &db, &q, answer.bm.toast(), &langs).await?;
+    let chat_id = match q.chat_id() {
+        Some(chat_id) => chat_id.0,
+        None => tguser.id.0 as i64,
+    };
+    update_script_state(state_mgr, chat_id, &answer.bm).await?;
 
-    let (chat_id, msg_id) = {
-        let chat_id = match q.chat_id() {
-            Some(chat_id) => chat_id.0,
-            None => tguser.id.0 as i64,
-        };
+    if !answer.is_propagate || answer.bm.is_toast_only() {
+        return Ok(());
+    }
 
-        let msg_id = q.message.map_or_else(
-            || {
-                Err(BotError::MsgTooOld(
-                    "Failed to get message id, probably message too old".to_string(),
-                ))
-            },
-            |m| Ok(m.id().0),
-        );
+    let msg_id = match (answer.bm.is_replace(), q.message) {
+        (true, Some(m)) => m.id().0,
+        // message is too old to get it's id, or should not be replaced
+        _ => return send_answer(&bot, &mut db, chat_id, &tguser, langs, &answer).await,
+    };
 
-        (chat_id, msg_id)
+    let layout = answer.bm.resolve_buttons(&mut db, &langs).await?;
+    let buttons = reply_markup(layout, answer.bm.keyboard_type(), &db).await?;
+    let ma = MessageAnswerer::new(&bot, &mut db, chat_id)
+        .with_context(answer.context(&tguser))
+        .with_langs(langs);
+    match &answer.text {
+        Some(text) => ma.replace_text(msg_id, text.clone(), buttons).await?,
+        None => {
+            ma.replace_message(msg_id, answer.literal(), buttons)
+                .await?
+        }
     };
 
-    let ma = MessageAnswerer::new(&bot, &mut db, chat_id);
-    match bm.is_replace() {
-        true => {
-            match msg_id {
-                Ok(msg_id) => {
-                    ma.replace_message(msg_id, literal, buttons).await?;
-                }
-                Err(_) => {
-                    ma.answer(literal, None, buttons).await?;
+    Ok(())
+}
+
+async fn handle_expired_callback<P: Provider>(
+    bot: Bot,
+    mut db: DB,
+    answer: ExpiredAnswer<P>,
+    q: CallbackQuery,
+    state_mgr: Arc<MongodbStorage<Json>>,
+    default_lang: DefaultLanguage,
+    relative: RelativeNotifications,
+) -> BotResult<()> {
+    let tguser = q.from.clone();
+    let langs = default_lang.langs(&tguser);
+    let bm = match answer {
+        ExpiredAnswer::Alert(literal) => {
+            let query = bot.answer_callback_query(&q.id);
+            match db.get_literal_value_lang(&literal, &langs).await? {
+                Some(text) => query.text(text).show_alert(true).await?,
+                None => {
+                    notify_admin(&format!("Literal `{literal}` is not set!!!")).await;
+                    query.await?
                 }
             };
+            return Ok(());
         }
-        false => {
-            ma.answer(literal, None, buttons).await?;
-        }
+        ExpiredAnswer::Message(bm) => bm,
+    };
+    bot.answer_callback_query(&q.id).await?;
+
+    let user = init_user(&mut db, &tguser, &relative).await?;
+
+    let mi = MessageInfoBuilder::new()
+        .set_metas(user.metas.clone())
+        .build();
+    let answer = run_handler(bm, None, &tguser, &mi).await?;
+    let chat_id = match q.chat_id() {
+        Some(chat_id) => chat_id.0,
+        None => tguser.id.0 as i64,
+    };
+    update_script_state(state_mgr, chat_id, &answer.bm).await?;
+
+    if !answer.is_propagate {
+        return Ok(());
     }
 
-    Ok(())
+    send_answer(&bot, &mut db, chat_id, &tguser, langs, &answer).await
+}
+
+async fn handle_stateful<P: Provider>(
+    bot: Bot,
+    mut db: DB,
+    bm: BotMessage<P>,
+    msg: Message,
+    state_mgr: Arc<MongodbStorage<Json>>,
+    default_lang: DefaultLanguage,
+    relative: RelativeNotifications,
+) -> BotResult<()> {
+    let tguser = match msg.from.clone() {
+        Some(user) => user,
+        None => return Ok(()), // do nothing, cause its not usecase of function
+    };
+    let user = init_user(&mut db, &tguser, &relative).await?;
+
+    let mi = MessageInfoBuilder::new()
+        .set_message(&msg)
+        .set_metas(user.metas.clone())
+        .build();
+    let answer = run_handler(bm, None, &tguser, &mi).await?;
+    update_script_state(state_mgr, msg.chat.id.0, &answer.bm).await?;
+
+    if !answer.is_propagate {
+        return Ok(());
+    }
+
+    let langs = default_lang.langs(&tguser);
+    send_answer(&bot, &mut db, msg.chat.id.0, &tguser, langs, &answer).await
 }
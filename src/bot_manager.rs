@@ -1,31 +1,111 @@
 use std::{
     collections::HashMap,
-    future::Future,
     sync::{Arc, Mutex},
     thread::JoinHandle,
     time::Duration,
 };
 
+use async_trait::async_trait;
+use chrono::Utc;
 use log::{error, info};
-use teloxide::{dispatching::dialogue::serializer::Json, dptree, prelude::Dispatcher, Bot};
+use teloxide::{
+    dispatching::dialogue::serializer::Json, dptree, prelude::Dispatcher, Bot, RequestError,
+};
 
 use crate::{
     bot_handler::{script_handler, BotHandler},
-    db::{bots::BotInstance, DbError, DB},
-    message_answerer::MessageAnswerer,
+    broadcast::{is_blocked, retry_after, Broadcast, BroadcastProgress},
+    config::Provider,
+    db::{
+        bots::{BotInstance, ScriptLang},
+        notification_delivery::{DeliveryStatus, NotificationDelivery, NotificationRun, RunStatus},
+        scheduled_message::ScheduledMessage,
+        CallDB, DbError, Langs, DB,
+    },
+    message_answerer::{MessageAnswerer, MessageAnswererError},
     mongodb_storage::MongodbStorage,
-    BotController, BotResult, BotRuntime,
+    runtimes::{mlua::LuaRuntime, v8::V8Runtime},
+    BotController, BotError, BotResult, BotRuntime,
 };
 
 pub type BotThread = JoinHandle<BotResult<()>>;
 
+/// how often notificator checks messages scheduled for users and deliveries to retry
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// runs, that were not started in this time after their schedule, are not sent
+const MISSED_RUN_LIMIT: Duration = Duration::from_secs(60 * 60);
+
+/// first delay before sending notification again, doubled on every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+/// Part of [`BotController`] that does not depend on runtime provider,
+/// so bots with scripts in different languages can live in the same pool
+#[async_trait]
+pub trait ScriptedBot: Send + Sync {
+    fn bot(&self) -> &Bot;
+
+    fn db(&self) -> &DB;
+
+    /// handler of script's dialog, with `plug_handlers` checked first
+    async fn script_handler(&self, plug_handlers: Vec<BotHandler>) -> BotHandler;
+
+    async fn spawn_notificator(&self) -> BotResult<BotThread>;
+}
+
+#[async_trait]
+impl<P: Provider> ScriptedBot for BotController<P> {
+    fn bot(&self) -> &Bot {
+        &self.bot
+    }
+
+    fn db(&self) -> &DB {
+        &self.db
+    }
+
+    async fn script_handler(&self, plug_handlers: Vec<BotHandler>) -> BotHandler {
+        script_handler_gen(self.runtime.clone(), plug_handlers).await
+    }
+
+    async fn spawn_notificator(&self) -> BotResult<BotThread> {
+        spawn_notificator_thread(self.clone()).await
+    }
+}
+
+/// creates controller with runtime of script's language
+pub async fn create_controller(db: DB, bi: &BotInstance) -> BotResult<Box<dyn ScriptedBot>> {
+    let controller: Box<dyn ScriptedBot> = match bi.lang {
+        ScriptLang::Js => {
+            Box::new(BotController::<V8Runtime>::with_db(db, &bi.token, &bi.script).await?)
+        }
+        ScriptLang::Luau => {
+            Box::new(BotController::<LuaRuntime>::with_db(db, &bi.token, &bi.script).await?)
+        }
+    };
+
+    Ok(controller)
+}
+
 pub struct BotRunner {
-    controller: BotController,
+    controller: Box<dyn ScriptedBot>,
     info: BotInfo,
     notificator: NotificatorThread,
     thread: Option<BotThread>,
 }
 
+impl BotRunner {
+    pub fn new(controller: Box<dyn ScriptedBot>, info: BotInfo) -> Self {
+        Self {
+            controller,
+            info,
+            notificator: NotificatorThread::Running(None),
+            thread: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum NotificatorThread {
     Running(Option<BotThread>),
@@ -99,15 +179,14 @@ where
                     Some(thread) => Some(thread),
                     None => {
                         let handlers = (self.h_mapper)(bi.clone()).await;
-                        let handler = script_handler_gen(
-                            bot_runner.controller.runtime.clone(),
-                            handlers.collect(),
-                        )
-                        .await;
+                        let handler = bot_runner
+                            .controller
+                            .script_handler(handlers.collect())
+                            .await;
                         Some(
                             spawn_bot_thread(
-                                bot_runner.controller.bot.clone(),
-                                bot_runner.controller.db.clone(),
+                                bot_runner.controller.bot().clone(),
+                                bot_runner.controller.db().clone(),
                                 handler,
                             )
                             .await?,
@@ -123,8 +202,7 @@ where
                         NotificatorThread::Running(match thread {
                             Some(thread) => Some(thread),
                             None => {
-                                let thread =
-                                    spawn_notificator_thread(bot_runner.controller.clone()).await?;
+                                let thread = bot_runner.controller.spawn_notificator().await?;
                                 Some(thread)
                             }
                         })
@@ -143,19 +221,13 @@ where
         db: &mut DB,
     ) -> BotResult<BotRunner> {
         let db = db.clone().with_name(bi.name.clone());
-        let controller = BotController::with_db(db.clone(), &bi.token, &bi.script).await?;
+        let controller = create_controller(db, bi).await?;
 
         let info = BotInfo {
             name: bi.name.clone(),
         };
-        let runner = BotRunner {
-            controller,
-            info,
-            notificator: NotificatorThread::Running(None),
-            thread: None,
-        };
 
-        Ok(runner)
+        Ok(BotRunner::new(controller, info))
     }
 }
 
@@ -195,8 +267,8 @@ fn check_notificator_done(n: NotificatorThread) -> NotificatorThread {
     }
 }
 
-async fn script_handler_gen(
-    r: Arc<Mutex<BotRuntime>>,
+async fn script_handler_gen<P: Provider>(
+    r: Arc<Mutex<BotRuntime<P>>>,
     plug_handlers: Vec<BotHandler>,
 ) -> BotHandler {
     let handler = script_handler(r.clone());
@@ -231,38 +303,265 @@ pub async fn spawn_bot_thread(bot: Bot, mut db: DB, handler: BotHandler) -> BotR
     Ok(thread)
 }
 
-pub async fn spawn_notificator_thread(mut c: BotController) -> BotResult<BotThread> {
+pub async fn spawn_notificator_thread<P: Provider>(
+    mut c: BotController<P>,
+) -> BotResult<BotThread> {
     let thread = std::thread::spawn(move || -> BotResult<()> {
         let rt = tokio::runtime::Runtime::new()?;
 
         rt.block_on(async {
+            let interrupted = NotificationDelivery::fail_interrupted(&mut c.db).await?;
+            if interrupted > 0 {
+                error!("Notificator: {interrupted} notifications were interrupted while sending");
+            }
+
             loop {
-                let notifications = {
+                // first, since runs could be left by previous notificator
+                start_due_runs(&mut c).await?;
+                deliver_scheduled_messages(&mut c).await?;
+                let default_lang = {
                     let r = c.runtime.lock().expect("Poisoned Runtime lock");
-                    r.rc.get_nearest_notifications()
+                    r.rc.default_language().map(str::to_string)
                 };
+                send_due_deliveries(&mut c, default_lang.as_deref()).await?;
 
-                match notifications {
-                    Some(n) => {
-                        // waiting time to send notification
-                        tokio::time::sleep(n.wait_for()).await;
-                        'n: for n in n.notifications().iter() {
-                            for user in n.get_users(&c.db).await?.into_iter() {
-                                let text = match n.resolve_message(&c.db, &user).await? {
-                                    Some(text) => text,
-                                    None => continue 'n,
-                                };
-
-                                let ma = MessageAnswerer::new(&c.bot, &mut c.db, user.id);
-                                ma.answer_text(text.clone(), None).await?;
-                            }
-                        }
+                let (notifications, has_relative) = {
+                    let r = c.runtime.lock().expect("Poisoned Runtime lock");
+                    (
+                        r.rc.get_nearest_notifications(),
+                        !r.rc.relative_notifications().is_empty(),
+                    )
+                };
+                if let Some(ref n) = notifications {
+                    for (name, notification) in n.notifications() {
+                        let run = match notification.is_once_after_start() {
+                            true => NotificationRun::once(name.clone(), n.at()),
+                            false => NotificationRun::new(name.clone(), n.at()),
+                        };
+                        run.schedule(&mut c.db).await?;
                     }
-                    None => break Ok(()),
                 }
+                let has_pending = NotificationDelivery::has_pending(&mut c.db).await?;
+
+                let wait_for = match (notifications, has_relative || has_pending) {
+                    (None, false) => break Ok(()),
+                    (Some(n), false) => n.wait_for(),
+                    (Some(n), true) => n.wait_for().min(CHECK_INTERVAL),
+                    (None, true) => CHECK_INTERVAL,
+                };
+                // waiting time to send notification
+                tokio::time::sleep(wait_for).await;
             }
         })
     });
 
     Ok(thread)
 }
+
+/// creates deliveries of notifications' runs, which time has come
+async fn start_due_runs<P: Provider>(c: &mut BotController<P>) -> BotResult<()> {
+    let now = Utc::now();
+    for run in NotificationRun::get_due(&mut c.db, now).await? {
+        let notification = {
+            let r = c.runtime.lock().expect("Poisoned Runtime lock");
+            r.rc.get_notification(&run.notification)
+        };
+        let late = (now - run.scheduled_at)
+            .to_std()
+            .is_ok_and(|late| late > MISSED_RUN_LIMIT);
+        // notification could be removed from config since run was scheduled
+        let notification = match notification {
+            Some(notification) if !late => notification,
+            _ => {
+                run.set_status(&mut c.db, RunStatus::Missed).await?;
+                continue;
+            }
+        };
+
+        let users = match notification.get_users(&c.db).await {
+            Ok(users) => users,
+            // trying again later, until run is missed
+            Err(err) => {
+                error!(
+                    "Notificator: failed to get users of `{}`: {err}",
+                    run.notification
+                );
+                continue;
+            }
+        };
+        let deliveries = users
+            .iter()
+            .map(|user| {
+                NotificationDelivery::new(run._id.clone(), run.notification.clone(), user.id)
+            })
+            .collect();
+        NotificationDelivery::store_many(&mut c.db, deliveries).await?;
+        run.set_status(&mut c.db, RunStatus::Started).await?;
+    }
+
+    Ok(())
+}
+
+/// creates deliveries of messages, which timers were started by users' events
+async fn deliver_scheduled_messages<P: Provider>(c: &mut BotController<P>) -> BotResult<()> {
+    let scheduled = ScheduledMessage::get_due(&mut c.db, Utc::now()).await?;
+    let deliveries = scheduled
+        .iter()
+        .map(|sm| NotificationDelivery::new(sm._id.to_hex(), sm.notification.clone(), sm.user_id))
+        .collect();
+    NotificationDelivery::store_many(&mut c.db, deliveries).await?;
+    for sm in scheduled {
+        sm.mark_sent(&mut c.db).await?;
+    }
+
+    Ok(())
+}
+
+/// sends pending deliveries, failed ones are retried later if error is transient
+async fn send_due_deliveries<P: Provider>(
+    c: &mut BotController<P>,
+    default_lang: Option<&str>,
+) -> BotResult<()> {
+    let due = NotificationDelivery::get_due(&mut c.db, Utc::now()).await?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    let due = due.into_iter().map(|d| (d.user_id, d)).collect();
+
+    let (bot, db, runtime) = (&c.bot, &c.db, &c.runtime);
+    let results = Broadcast::new(bot)
+        .run(
+            due,
+            |_, delivery| async move {
+                let mut db = db.clone();
+                claim_and_send(bot, &mut db, runtime, delivery, default_lang).await
+            },
+            |_| {},
+        )
+        .await;
+
+    let mut progress = BroadcastProgress {
+        total: results.len(),
+        ..Default::default()
+    };
+    let mut retried = 0;
+    // one user's failure should not stop delivery to the others
+    for (delivery, result) in results {
+        let (status, error) = match result {
+            // is not pending anymore, e.g. was claimed by another notificator
+            Ok(None) => continue,
+            Ok(Some(true)) => (DeliveryStatus::Sent, None),
+            Ok(Some(false)) => (DeliveryStatus::Skipped, None),
+            // user won't get the next notifications either
+            Err(err) if is_blocked(&err) => {
+                c.db.set_user_blocked(delivery.user_id, true).await?;
+                (DeliveryStatus::Failed, Some(err.to_string()))
+            }
+            // delivery was claimed before sending, so it's one more attempt
+            Err(err) => match retry_delay(&err, delivery.attempts + 1) {
+                Some(delay) => {
+                    let at = Utc::now() + delay;
+                    delivery.retry_at(&mut c.db, at, err.to_string()).await?;
+                    retried += 1;
+                    continue;
+                }
+                None => (DeliveryStatus::Failed, Some(err.to_string())),
+            },
+        };
+        match status {
+            DeliveryStatus::Failed => progress.failed += 1,
+            _ => progress.sent += 1,
+        };
+        delivery.set_status(&mut c.db, status, error).await?;
+    }
+    info!("Notificator: deliveries are processed, {progress}, retried: {retried}");
+
+    Ok(())
+}
+
+/// claims delivery right before sending, so deliveries, that are waiting for rate limiter,
+/// are still pending if notificator is stopped. Returns `None` if delivery is not pending anymore
+async fn claim_and_send<P: Provider>(
+    bot: &Bot,
+    db: &mut DB,
+    runtime: &Mutex<BotRuntime<P>>,
+    mut delivery: NotificationDelivery,
+    default_lang: Option<&str>,
+) -> BotResult<Option<bool>> {
+    if !delivery.claim(db).await? {
+        return Ok(None);
+    }
+
+    match send_delivery(bot, db, runtime, &delivery, default_lang).await {
+        Ok(sent) => Ok(Some(sent)),
+        Err(err) => {
+            // broadcast sends it again after a while, so it should be claimed again
+            if retry_after(&err).is_some() {
+                delivery.retry_at(db, Utc::now(), err.to_string()).await?;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// returns false, if notification has no message for user, or user has blocked bot
+async fn send_delivery<P: Provider>(
+    bot: &Bot,
+    db: &mut DB,
+    runtime: &Mutex<BotRuntime<P>>,
+    delivery: &NotificationDelivery,
+    default_lang: Option<&str>,
+) -> BotResult<bool> {
+    let notification = {
+        let r = runtime.lock().expect("Poisoned Runtime lock");
+        r.rc.get_notification(&delivery.notification)
+    };
+    let notification = notification.ok_or_else(|| {
+        BotError::BotLogicError(format!(
+            "notification `{}` is not found",
+            delivery.notification
+        ))
+    })?;
+    let user = db.get_users_by_ids(vec![delivery.user_id]).await?.pop();
+    let user = user.ok_or_else(|| {
+        BotError::BotLogicError(format!("user `{}` is not found", delivery.user_id))
+    })?;
+
+    // user has blocked bot after message was scheduled
+    if user.blocked_at.is_some() {
+        return Ok(false);
+    }
+
+    let langs = Langs::new(user.language_code.as_deref(), default_lang);
+    let text = match notification.resolve_message(db, &user, &langs).await? {
+        Some(text) => text,
+        None => return Ok(false),
+    };
+
+    let ma = MessageAnswerer::new(bot, db, user.id).with_langs(langs);
+    ma.answer_text(text, None).await?;
+
+    Ok(true)
+}
+
+/// delay before the next attempt of delivery, `None` if error is not transient,
+/// or all attempts are used
+fn retry_delay(err: &BotError, attempts: u32) -> Option<Duration> {
+    if attempts >= MAX_DELIVERY_ATTEMPTS {
+        return None;
+    }
+
+    if let Some(delay) = retry_after(err) {
+        return Some(delay);
+    }
+    let transient = match err {
+        BotError::TeloxideError(err)
+        | BotError::MAError(MessageAnswererError::RequestError(err)) => {
+            matches!(err, RequestError::Network(_) | RequestError::Io(_))
+        }
+        BotError::DBError(_) | BotError::MAError(MessageAnswererError::DbError(_)) => true,
+        _ => false,
+    };
+
+    transient.then(|| RETRY_DELAY * 2_u32.pow(attempts.saturating_sub(1)))
+}
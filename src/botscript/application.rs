@@ -1,59 +1,61 @@
-use quickjs_rusty::{context::Context, serde::from_js, OwnedJsObject};
+use serde_json::Value;
 use teloxide::Bot;
-use tokio::runtime::Handle;
 
 use crate::{
+    config::notification::relative::{RelativeNotifications, UserEvent},
     db::{application::Application, message_forward::MessageForward, DB},
     message_answerer::MessageAnswerer,
     send_application_to_chat,
 };
 
-use super::ScriptError;
+use super::host::{arg, HostApi};
+use super::{DefaultLang, ScriptError, ScriptResult};
 
 pub fn attach_user_application(
-    c: &Context,
-    o: &mut OwnedJsObject,
-    db: DB,
-    bot: Bot,
+    host: &mut HostApi,
+    db: &DB,
+    bot: &Bot,
+    relative: &RelativeNotifications,
+    default_lang: &DefaultLang,
 ) -> Result<(), ScriptError> {
-    // To guarantee that closure is valid if thread panics
-    let db: std::sync::Mutex<DB> = std::sync::Mutex::new(db);
-    let bot: std::sync::Mutex<Bot> = std::sync::Mutex::new(bot);
-
-    let user_application =
-        c.create_callback(move |q: OwnedJsObject| -> Result<_, ScriptError> {
-            let mut db = { db.lock().map_err(ScriptError::from)?.clone() };
-            let bot = { bot.lock().map_err(ScriptError::from)?.clone() };
-            let user: teloxide::types::User = match from_js(q.context(), &q) {
-                Ok(q) => q,
-                Err(_) => todo!(),
-            };
-
-            let application =
-                futures::executor::block_on(Application::new(user.clone()).store_db(&mut db))?;
-
-            let msg = tokio::task::block_in_place(|| {
-                Handle::current()
-                    .block_on(async { send_application_to_chat(&bot, &mut db, &application).await })
-            });
-            let msg = msg.map_err(ScriptError::from)?;
-
-            let (chat_id, msg_id) = tokio::task::block_in_place(|| {
-                Handle::current().block_on(async {
-                    MessageAnswerer::new(&bot, &mut db, user.id.0 as i64)
-                        .answer("left_application_msg", None, None)
-                        .await
-                })
-            })?;
-            futures::executor::block_on(
-                MessageForward::new(msg.chat.id.0, msg.id.0, chat_id, msg_id, false)
-                    .store_db(&mut db),
-            )?;
+    let (db_, bot_, relative_) = (db.clone(), bot.clone(), relative.clone());
+    let default_lang_ = default_lang.clone();
+    host.set_async_function("user_application", move |args| {
+        let (mut db, bot, relative) = (db_.clone(), bot_.clone(), relative_.clone());
+        let default_lang = default_lang_.get();
+        async move {
+            let user: teloxide::types::User = arg(&args, 0)?;
+            let user_id = user.id.0 as i64;
+
+            user_application(&bot, &mut db, user, default_lang).await?;
+            relative
+                .schedule(&mut db, user_id, &UserEvent::Application)
+                .await?;
 
             let ret = true;
-            Ok(ret)
-        })?;
+            Ok(Value::Bool(ret))
+        }
+    });
+
+    Ok(())
+}
+
+async fn user_application(
+    bot: &Bot,
+    db: &mut DB,
+    user: teloxide::types::User,
+    default_lang: Option<String>,
+) -> ScriptResult<()> {
+    let application = Application::new(user.clone()).store_db(db).await?;
+    let msg = send_application_to_chat(bot, db, &application).await?;
+
+    let (chat_id, msg_id) = MessageAnswerer::new(bot, db, user.id.0 as i64)
+        .with_default_lang(default_lang)
+        .answer("left_application_msg", None, None)
+        .await?;
+    MessageForward::new(msg.chat.id.0, msg.id.0, chat_id, msg_id, false)
+        .store_db(db)
+        .await?;
 
-    o.set_property("user_application", user_application.into_value())?;
     Ok(())
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+use teloxide::Bot;
+
+use crate::config::dialog::button::{ButtonLayout, ButtonRaw};
+use crate::db::{Langs, DB};
+use crate::message_answerer::MessageAnswerer;
+use crate::utils::inline_keyboard;
+use crate::utils::template::TemplateContext;
+
+use super::host::{arg, opt_arg, HostApi};
+use super::{DefaultLang, ScriptError, ScriptResult};
+
+/// message, that script can send: `{literal, variant, vars}`, `{text}` or just a literal name
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScriptMessage {
+    Literal {
+        literal: String,
+        variant: Option<String>,
+        /// values of `{var.<name>}` placeholders
+        #[serde(default)]
+        vars: HashMap<String, Value>,
+    },
+    Text {
+        text: String,
+    },
+    LiteralName(String),
+}
+
+pub fn attach_bot_obj(
+    host: &mut HostApi,
+    db: &DB,
+    bot: &Bot,
+    default_lang: &DefaultLang,
+) -> Result<(), ScriptError> {
+    let (db_, bot_, default_lang_) = (db.clone(), bot.clone(), default_lang.clone());
+    host.set_async_method("bot", "send", move |args| {
+        let (mut db, bot) = (db_.clone(), bot_.clone());
+        let default_lang = default_lang_.get();
+        async move {
+            let user_id: i64 = arg(&args, 0)?;
+            let message: ScriptMessage = arg(&args, 1)?;
+            let buttons: Option<Vec<Vec<ButtonRaw>>> = opt_arg(&args, 2)?;
+
+            let langs = Langs::of_user(&db, user_id, default_lang.as_deref()).await?;
+            let (_, msg_id) = send_message(&bot, &mut db, user_id, langs, message, buttons).await?;
+            Ok(Value::from(msg_id))
+        }
+    });
+
+    let (db_, bot_) = (db.clone(), bot.clone());
+    // deletes the whole album with its keyboard, if message is a part of it
+    host.set_async_method("bot", "delete", move |args| {
+        let (mut db, bot) = (db_.clone(), bot_.clone());
+        async move {
+            let chat_id: i64 = arg(&args, 0)?;
+            let message_id: i32 = arg(&args, 1)?;
+
+            MessageAnswerer::new(&bot, &mut db, chat_id)
+                .delete(message_id)
+                .await?;
+            Ok(Value::Null)
+        }
+    });
+
+    Ok(())
+}
+
+async fn send_message(
+    bot: &Bot,
+    db: &mut DB,
+    chat_id: i64,
+    langs: Langs,
+    message: ScriptMessage,
+    buttons: Option<Vec<Vec<ButtonRaw>>>,
+) -> ScriptResult<(i64, i32)> {
+    let keyboard = match buttons {
+        Some(rows) => {
+            let mut layout = Vec::with_capacity(rows.len());
+            for row in rows {
+                let mut lrow = Vec::with_capacity(row.len());
+                for button in row {
+                    lrow.push(ButtonLayout::resolve_raw(button, db, &langs).await?);
+                }
+                layout.push(lrow);
+            }
+            Some(inline_keyboard(layout, db).await?.into())
+        }
+        None => None,
+    };
+
+    let ma = MessageAnswerer::new(bot, db, chat_id).with_langs(langs);
+    let ids = match message {
+        ScriptMessage::Literal {
+            literal,
+            variant,
+            vars,
+        } => {
+            ma.with_context(TemplateContext::default().with_vars(vars))
+                .answer(&literal, variant.as_deref(), keyboard)
+                .await?
+        }
+        ScriptMessage::Text { text } => ma.answer_text(text, keyboard).await?,
+        ScriptMessage::LiteralName(literal) => ma.answer(&literal, None, keyboard).await?,
+    };
+
+    Ok(ids)
+}
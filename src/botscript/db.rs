@@ -1,48 +1,70 @@
-use std::sync::RwLock;
-
-use quickjs_rusty::context::Context;
-
-use quickjs_rusty::serde::{from_js, to_js};
-use quickjs_rusty::{utils::create_empty_object, OwnedJsObject, OwnedJsValue as JsValue};
+use serde_json::Value;
 
 use crate::db::raw_calls::RawCall;
 use crate::db::DB;
 
+use super::host::{arg, HostApi};
 use super::ScriptError;
 
-pub fn attach_db_obj(c: &Context, o: &mut OwnedJsObject, db: &DB) -> Result<(), ScriptError> {
-    let dbobj = JsValue::new(o.context(), create_empty_object(o.context())?)
-        .try_into_object()
-        .expect("the created object was not an object :/");
-
-    let db: std::sync::Arc<RwLock<DB>> = std::sync::Arc::new(RwLock::new(db.clone()));
-
-    let find_one = c.create_callback(
-        move |collection: String, q: OwnedJsObject| -> Result<_, ScriptError> {
-            // let db = db.clone();
-            let query: serde_json::Value = match from_js(q.context(), &q) {
-                Ok(q) => q,
-                Err(_) => todo!(),
-            };
-
-            let value = futures::executor::block_on(
-                db.write()
-                    .expect("failed to gain write acces to db (probably RwLock is poisoned)")
-                    .find_one(&collection, query),
-            )?;
-
-            let ret = match value {
-                Some(v) => Some(to_js(q.context(), &v)?),
-                None => None,
-            };
-            Ok(ret)
-        },
-    )?;
-    let find_one = JsValue::from((unsafe { c.context_raw() }, find_one));
-
-    dbobj.set_property("find_one", find_one)?;
-
-    o.set_property("db", dbobj.into_value())?;
+/// scripts are allowed to work only with their own collections,
+/// so bot's data (users, literals, etc.) can't be broken by script
+fn script_collection(collection: &str) -> String {
+    format!("script_{collection}")
+}
+
+pub fn attach_db_obj(host: &mut HostApi, db: &DB) -> Result<(), ScriptError> {
+    let db_ = db.clone();
+    host.set_async_method("db", "find_one", move |args| {
+        let mut db = db_.clone();
+        async move {
+            let collection: String = arg(&args, 0)?;
+            let query: Value = arg(&args, 1)?;
+
+            let value = db.find_one(&script_collection(&collection), query).await?;
+            Ok(value.unwrap_or(Value::Null))
+        }
+    });
+
+    let db_ = db.clone();
+    host.set_async_method("db", "find", move |args| {
+        let mut db = db_.clone();
+        async move {
+            let collection: String = arg(&args, 0)?;
+            let query: Value = arg(&args, 1)?;
+
+            let values = db.find(&script_collection(&collection), query).await?;
+            Ok(Value::Array(values))
+        }
+    });
+
+    let db_ = db.clone();
+    host.set_async_method("db", "insert", move |args| {
+        let mut db = db_.clone();
+        async move {
+            let collection: String = arg(&args, 0)?;
+            let document: Value = arg(&args, 1)?;
+
+            let id = db
+                .insert_one(&script_collection(&collection), document)
+                .await?;
+            Ok(id)
+        }
+    });
+
+    let db_ = db.clone();
+    host.set_async_method("db", "update", move |args| {
+        let mut db = db_.clone();
+        async move {
+            let collection: String = arg(&args, 0)?;
+            let query: Value = arg(&args, 1)?;
+            let update: Value = arg(&args, 2)?;
+
+            let modified = db
+                .update_many(&script_collection(&collection), query, update)
+                .await?;
+            Ok(modified.into())
+        }
+    });
 
     Ok(())
 }
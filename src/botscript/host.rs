@@ -0,0 +1,135 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use futures::{future::BoxFuture, FutureExt};
+use log::info;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{ScriptError, ScriptResult};
+
+pub type SyncHostFn = Arc<dyn Fn(Vec<Value>) -> ScriptResult<Value> + Send + Sync>;
+pub type AsyncHostFn =
+    Arc<dyn Fn(Vec<Value>) -> BoxFuture<'static, ScriptResult<Value>> + Send + Sync>;
+
+/// Function provided to script.
+///
+/// Async functions are not blocking the runtime: they return promise in js
+/// and yield current coroutine in lua, while future is running on tokio
+#[derive(Clone)]
+pub enum HostFunction {
+    Sync(SyncHostFn),
+    Async(AsyncHostFn),
+}
+
+/// Functions and objects provided by bot to scripts, independent of runtime.
+///
+/// Every runtime puts functions in script's global scope, and methods
+/// as functions of global objects, so `db.find_one` is called from script as it is
+#[derive(Clone, Default)]
+pub struct HostApi {
+    functions: HashMap<String, HostFunction>,
+    objects: HashMap<String, HashMap<String, HostFunction>>,
+}
+
+impl HostFunction {
+    fn async_fn<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ScriptResult<Value>> + Send + 'static,
+    {
+        Self::Async(Arc::new(move |args| f(args).boxed()))
+    }
+}
+
+impl HostApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_function<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(Vec<Value>) -> ScriptResult<Value> + Send + Sync + 'static,
+    {
+        self.functions
+            .insert(name.to_string(), HostFunction::Sync(Arc::new(f)));
+    }
+
+    pub fn set_async_function<F, Fut>(&mut self, name: &str, f: F)
+    where
+        F: Fn(Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ScriptResult<Value>> + Send + 'static,
+    {
+        self.functions
+            .insert(name.to_string(), HostFunction::async_fn(f));
+    }
+
+    pub fn set_method<F>(&mut self, object: &str, name: &str, f: F)
+    where
+        F: Fn(Vec<Value>) -> ScriptResult<Value> + Send + Sync + 'static,
+    {
+        self.set_object_function(object, name, HostFunction::Sync(Arc::new(f)));
+    }
+
+    pub fn set_async_method<F, Fut>(&mut self, object: &str, name: &str, f: F)
+    where
+        F: Fn(Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ScriptResult<Value>> + Send + 'static,
+    {
+        self.set_object_function(object, name, HostFunction::async_fn(f));
+    }
+
+    fn set_object_function(&mut self, object: &str, name: &str, f: HostFunction) {
+        self.objects
+            .entry(object.to_string())
+            .or_default()
+            .insert(name.to_string(), f);
+    }
+
+    /// all of host functions as (object, name, function),
+    /// where object is None for global functions
+    pub fn entries(&self) -> impl Iterator<Item = (Option<&str>, &str, &HostFunction)> {
+        let functions = self
+            .functions
+            .iter()
+            .map(|(name, f)| (None, name.as_str(), f));
+        let methods = self.objects.iter().flat_map(|(object, methods)| {
+            methods
+                .iter()
+                .map(move |(name, f)| (Some(object.as_str()), name.as_str(), f))
+        });
+
+        functions.chain(methods)
+    }
+}
+
+/// get deserialized argument of host function by its position
+pub fn arg<T: DeserializeOwned>(args: &[Value], i: usize) -> ScriptResult<T> {
+    let value = args.get(i).cloned().unwrap_or(Value::Null);
+
+    serde_json::from_value(value)
+        .map_err(|err| ScriptError::Other(format!("wrong argument #{i} passed, err: {err}")))
+}
+
+/// get deserialized argument of host function, if it is passed
+pub fn opt_arg<T: DeserializeOwned>(args: &[Value], i: usize) -> ScriptResult<Option<T>> {
+    match args.get(i) {
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => arg(args, i).map(Some),
+    }
+}
+
+pub fn attach_print(host: &mut HostApi) {
+    host.set_function("print", |args| {
+        let text = args
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => s,
+                other => other.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!("script: {text}");
+
+        Ok(Value::Null)
+    });
+}
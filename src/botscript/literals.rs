@@ -0,0 +1,37 @@
+use serde_json::Value;
+
+use crate::db::{CallDB, Langs, DB};
+
+use super::host::{arg, opt_arg, HostApi};
+use super::ScriptError;
+
+pub fn attach_literals_obj(host: &mut HostApi, db: &DB) -> Result<(), ScriptError> {
+    let db_ = db.clone();
+    host.set_async_method("literals", "get", move |args| {
+        let db = db_.clone();
+        async move {
+            let literal: String = arg(&args, 0)?;
+            let lang: Option<String> = opt_arg(&args, 1)?;
+
+            let langs = Langs::new(lang.as_deref(), None);
+            let value = db.get_literal_value_lang(&literal, &langs).await?;
+            Ok(value.map_or(Value::Null, Value::String))
+        }
+    });
+
+    let db_ = db.clone();
+    host.set_async_method("literals", "set", move |args| {
+        let mut db = db_.clone();
+        async move {
+            let literal: String = arg(&args, 0)?;
+            let value: String = arg(&args, 1)?;
+            let lang: Option<String> = opt_arg(&args, 2)?;
+
+            db.set_literal_lang(&literal, lang.as_deref(), &value)
+                .await?;
+            Ok(Value::Null)
+        }
+    });
+
+    Ok(())
+}
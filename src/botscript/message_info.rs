@@ -1,8 +1,116 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use teloxide::types::{ChatKind, Contact, Location, Message, PublicChatKind};
+use teloxide::utils::render::RenderMessageTextHelper;
 
+use crate::db::MediaType;
+
+/// Context of update, that is passed to script's handlers
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct MessageInfo {
     variant: Option<String>,
+    chat: Option<ChatInfo>,
+    message_id: Option<i32>,
+    /// text or caption of user's message
+    text: Option<String>,
+    /// the same as `text`, but with formatting as html
+    html: Option<String>,
+    /// args of command, split by whitespaces
+    args: Vec<String>,
+    callback: Option<CallbackInfo>,
+    reply_to: Option<ReplyInfo>,
+    media: Vec<MediaInfo>,
+    contact: Option<Contact>,
+    location: Option<Location>,
+    /// user's metas stored in db
+    metas: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatInfo {
+    id: i64,
+    /// one of `private`, `group`, `supergroup`, `channel`
+    r#type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CallbackInfo {
+    /// callback name from config
+    name: String,
+    data: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplyInfo {
+    message_id: i32,
+    from_id: Option<i64>,
+    text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaInfo {
+    file_id: String,
+    /// the same types as used for literal's media, e.g. `photo`, `video`
+    media_type: MediaType,
+}
+
+impl ChatInfo {
+    fn from_message(msg: &Message) -> Self {
+        let r#type = match &msg.chat.kind {
+            ChatKind::Private(_) => "private",
+            ChatKind::Public(public) => match public.kind {
+                PublicChatKind::Group => "group",
+                PublicChatKind::Supergroup(_) => "supergroup",
+                PublicChatKind::Channel(_) => "channel",
+            },
+        };
+
+        Self {
+            id: msg.chat.id.0,
+            r#type: r#type.to_string(),
+        }
+    }
+}
+
+impl ReplyInfo {
+    fn from_message(msg: &Message) -> Self {
+        Self {
+            message_id: msg.id.0,
+            from_id: msg.from.as_ref().map(|user| user.id.0 as i64),
+            text: message_text(msg),
+        }
+    }
+}
+
+fn message_text(msg: &Message) -> Option<String> {
+    msg.text().or(msg.caption()).map(str::to_string)
+}
+
+fn message_media(msg: &Message) -> Vec<MediaInfo> {
+    let media = [
+        // the biggest size of photo is the last
+        (
+            MediaType::Photo,
+            msg.photo().and_then(|p| p.last()).map(|p| &p.file),
+        ),
+        (MediaType::Video, msg.video().map(|v| &v.file)),
+        (MediaType::Document, msg.document().map(|d| &d.file)),
+        (MediaType::Animation, msg.animation().map(|a| &a.file)),
+        (MediaType::Audio, msg.audio().map(|a| &a.file)),
+        (MediaType::Voice, msg.voice().map(|v| &v.file)),
+        (MediaType::VideoNote, msg.video_note().map(|v| &v.file)),
+        (MediaType::Sticker, msg.sticker().map(|s| &s.file)),
+    ];
+
+    media
+        .into_iter()
+        .filter_map(|(media_type, file)| {
+            file.map(|file| MediaInfo {
+                file_id: file.id.to_string(),
+                media_type,
+            })
+        })
+        .collect()
 }
 
 pub struct MessageInfoBuilder {
@@ -27,6 +135,34 @@ impl MessageInfoBuilder {
         self
     }
 
+    /// fills everything, that can be taken from message itself
+    pub fn set_message(mut self, msg: &Message) -> Self {
+        self.inner.chat = Some(ChatInfo::from_message(msg));
+        self.inner.message_id = Some(msg.id.0);
+        self.inner.text = message_text(msg);
+        self.inner.html = msg.html_text().or(msg.html_caption());
+        self.inner.reply_to = msg.reply_to_message().map(ReplyInfo::from_message);
+        self.inner.media = message_media(msg);
+        self.inner.contact = msg.contact().cloned();
+        self.inner.location = msg.location().cloned();
+        self
+    }
+
+    pub fn set_args(mut self, args: Vec<String>) -> Self {
+        self.inner.args = args;
+        self
+    }
+
+    pub fn set_callback(mut self, name: String, data: Option<Value>) -> Self {
+        self.inner.callback = Some(CallbackInfo { name, data });
+        self
+    }
+
+    pub fn set_metas(mut self, metas: Vec<String>) -> Self {
+        self.inner.metas = metas;
+        self
+    }
+
     pub fn build(self) -> MessageInfo {
         self.inner
     }
@@ -0,0 +1,346 @@
+//! Sending messages to many chats within telegram's limits: about 30 messages
+//! per second for a bot and about 1 message per second for a single chat
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::{stream, StreamExt};
+use lazy_static::lazy_static;
+use teloxide::{ApiError, Bot, RequestError};
+
+use crate::{message_answerer::MessageAnswererError, BotError, BotResult};
+
+/// messages per second, that bot can send
+pub const GLOBAL_RATE: f64 = 30.0;
+/// messages per second, that bot can send to one chat
+pub const CHAT_RATE: f64 = 1.0;
+/// messages, that are sent at the same time
+pub const DEFAULT_CONCURRENCY: usize = 8;
+/// how many times message is sent again, if telegram asks to retry after a while
+const MAX_RETRY_AFTER: u32 = 3;
+/// buckets of chats are cleared, when there are more of them
+const MAX_CHAT_BUCKETS: usize = 10_000;
+
+lazy_static! {
+    /// limiters by bot's token, shared by every [`Broadcast`] of the same bot, e.g. notificator's
+    /// and admin's ones. Replies of handlers are not sent through them
+    static ref LIMITERS: Mutex<HashMap<String, Arc<RateLimiter>>> = Default::default();
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    /// tokens per second
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, rate: f64, now: Instant) -> Self {
+        Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let passed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + passed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// time to wait until token is available
+    fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+}
+
+struct LimiterState {
+    global: TokenBucket,
+    chats: HashMap<i64, TokenBucket>,
+    /// set by telegram's `RetryAfter`, nothing is sent until then
+    paused_until: Option<Instant>,
+}
+
+/// Global and per chat token buckets
+pub struct RateLimiter {
+    chat_rate: f64,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(global_rate: f64, chat_rate: f64) -> Self {
+        Self {
+            chat_rate,
+            state: Mutex::new(LimiterState {
+                global: TokenBucket::new(global_rate, global_rate, Instant::now()),
+                chats: Default::default(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// limiter with telegram's limits, the same for every call with the same bot
+    pub fn for_bot(bot: &Bot) -> Arc<Self> {
+        let mut limiters = LIMITERS.lock().expect("Poisoned limiters lock");
+        limiters
+            .entry(bot.token().to_string())
+            .or_insert_with(|| Arc::new(Self::new(GLOBAL_RATE, CHAT_RATE)))
+            .clone()
+    }
+
+    /// time to wait before message can be sent to chat, takes tokens if it can be sent now
+    fn try_acquire(&self, chat_id: i64, now: Instant) -> Duration {
+        let mut state = self.state.lock().expect("Poisoned limiter lock");
+        if let Some(until) = state.paused_until {
+            if until > now {
+                return until - now;
+            }
+            state.paused_until = None;
+        }
+
+        if state.chats.len() > MAX_CHAT_BUCKETS {
+            state.chats.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+        }
+        let chat_rate = self.chat_rate;
+        let chat_wait = state
+            .chats
+            .entry(chat_id)
+            .or_insert_with(|| TokenBucket::new(1.0, chat_rate, now))
+            .wait_time(now);
+        let wait = state.global.wait_time(now).max(chat_wait);
+        if wait.is_zero() {
+            state.global.take();
+            if let Some(bucket) = state.chats.get_mut(&chat_id) {
+                bucket.take();
+            }
+        }
+
+        wait
+    }
+
+    /// waits until message can be sent to chat
+    pub async fn acquire(&self, chat_id: i64) {
+        loop {
+            let wait = self.try_acquire(chat_id, Instant::now());
+            if wait.is_zero() {
+                return;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// stops sending for a while, when telegram asks to
+    pub fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().expect("Poisoned limiter lock");
+        let until = Instant::now() + duration;
+        state.paused_until = Some(state.paused_until.map_or(until, |paused| paused.max(until)));
+    }
+}
+
+/// delay, that telegram asked to wait, because of too many requests
+pub fn retry_after(err: &BotError) -> Option<Duration> {
+    match err {
+        BotError::TeloxideError(RequestError::RetryAfter(secs))
+        | BotError::MAError(MessageAnswererError::RequestError(RequestError::RetryAfter(secs))) => {
+            Some(secs.duration())
+        }
+        _ => None,
+    }
+}
+
+/// user can't get messages: bot was blocked, chat was not found or user is deactivated
+pub fn is_blocked(err: &BotError) -> bool {
+    let err = match err {
+        BotError::TeloxideError(err)
+        | BotError::MAError(MessageAnswererError::RequestError(err)) => err,
+        _ => return false,
+    };
+
+    matches!(
+        err,
+        RequestError::Api(
+            ApiError::BotBlocked | ApiError::ChatNotFound | ApiError::UserDeactivated
+        )
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastProgress {
+    pub total: usize,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+impl std::fmt::Display for BroadcastProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sent: {}, failed: {}, total: {}",
+            self.sent, self.failed, self.total
+        )
+    }
+}
+
+/// Sends messages to many chats concurrently, within limits of [`RateLimiter`]
+pub struct Broadcast {
+    limiter: Arc<RateLimiter>,
+    concurrency: usize,
+}
+
+impl Broadcast {
+    pub fn new(bot: &Bot) -> Self {
+        Self {
+            limiter: RateLimiter::for_bot(bot),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// calls `send` for every chat with its item, `on_progress` is called after each of them.
+    /// Returns items with results in order of completion
+    pub async fn run<T, R, F, Fut>(
+        &self,
+        items: Vec<(i64, T)>,
+        send: F,
+        mut on_progress: impl FnMut(&BroadcastProgress),
+    ) -> Vec<(T, BotResult<R>)>
+    where
+        T: Clone,
+        F: Fn(i64, T) -> Fut,
+        Fut: Future<Output = BotResult<R>>,
+    {
+        let mut progress = BroadcastProgress {
+            total: items.len(),
+            ..Default::default()
+        };
+        let send = &send;
+        let mut sending = stream::iter(items)
+            .map(|(chat_id, item)| async move {
+                let result = self.send_one(chat_id, item.clone(), send).await;
+                (item, result)
+            })
+            .buffer_unordered(self.concurrency);
+
+        let mut results = Vec::with_capacity(progress.total);
+        while let Some((item, result)) = sending.next().await {
+            match result {
+                Ok(_) => progress.sent += 1,
+                Err(_) => progress.failed += 1,
+            };
+            on_progress(&progress);
+            results.push((item, result));
+        }
+
+        results
+    }
+
+    async fn send_one<T, R, F, Fut>(&self, chat_id: i64, item: T, send: &F) -> BotResult<R>
+    where
+        T: Clone,
+        F: Fn(i64, T) -> Fut,
+        Fut: Future<Output = BotResult<R>>,
+    {
+        let mut retries = 0;
+        loop {
+            self.limiter.acquire(chat_id).await;
+            let err = match send(chat_id, item.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            match retry_after(&err) {
+                Some(delay) if retries < MAX_RETRY_AFTER => {
+                    self.limiter.pause(delay);
+                    retries += 1;
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 1.0, start);
+
+        assert_eq!(bucket.wait_time(start), Duration::ZERO);
+        bucket.take();
+        bucket.take();
+        assert_eq!(bucket.wait_time(start), Duration::from_secs(1));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.wait_time(later), Duration::from_millis(500));
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.wait_time(later), Duration::ZERO);
+        assert!(bucket.is_full());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2.0, 1.0);
+        let now = Instant::now();
+
+        assert_eq!(limiter.try_acquire(1, now), Duration::ZERO);
+        // chat's limit
+        assert!(limiter.try_acquire(1, now) > Duration::ZERO);
+        assert_eq!(limiter.try_acquire(2, now), Duration::ZERO);
+        // global limit
+        assert!(limiter.try_acquire(3, now) > Duration::ZERO);
+
+        limiter.pause(Duration::from_secs(60));
+        let later = now + Duration::from_secs(10);
+        assert!(limiter.try_acquire(4, later) > Duration::from_secs(40));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let err = BotError::TeloxideError(RequestError::RetryAfter(
+            teloxide::types::Seconds::from_seconds(5),
+        ));
+        assert_eq!(retry_after(&err), Some(Duration::from_secs(5)));
+
+        let err = BotError::BotLogicError("some".to_string());
+        assert_eq!(retry_after(&err), None);
+    }
+
+    #[test]
+    fn test_is_blocked() {
+        let err = BotError::TeloxideError(RequestError::Api(ApiError::BotBlocked));
+        assert!(is_blocked(&err));
+        let err = BotError::MAError(MessageAnswererError::RequestError(RequestError::Api(
+            ApiError::UserDeactivated,
+        )));
+        assert!(is_blocked(&err));
+
+        let err = BotError::TeloxideError(RequestError::Api(ApiError::MessageNotModified));
+        assert!(!is_blocked(&err));
+        let err = BotError::BotLogicError("some".to_string());
+        assert!(!is_blocked(&err));
+    }
+}
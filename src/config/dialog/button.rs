@@ -1,4 +1,6 @@
-use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
 use crate::{
     config::{
@@ -7,7 +9,7 @@ use crate::{
         traits::{ProviderDeserialize, ResolveValue},
         Provider,
     },
-    db::{CallDB, DB},
+    db::{CallDB, Langs, DB},
     notify_admin,
 };
 
@@ -22,7 +24,59 @@ pub enum ButtonDefinition<P: Provider> {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ButtonRaw {
     name: ButtonName,
-    callback_name: String,
+    #[serde(flatten)]
+    kind: ButtonKind,
+}
+
+/// What button does, determined by the set field. Each kind has a required field,
+/// so button with misspelled fields is an error, not a text button
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ButtonKind {
+    Callback {
+        callback_name: String,
+        /// any json passed to callback's handler, so one callback can serve many buttons
+        #[serde(default)]
+        data: Option<Value>,
+    },
+    Url {
+        url: ButtonValue,
+    },
+    WebApp {
+        web_app: ButtonValue,
+    },
+    SwitchInline {
+        switch_inline: ButtonValue,
+        /// insert query in the current chat instead of choosing one
+        #[serde(default)]
+        current_chat: bool,
+    },
+    LoginUrl {
+        login_url: ButtonValue,
+    },
+    CopyText {
+        copy_text: ButtonValue,
+    },
+    /// only for reply keyboard, sends user's contact, `false` is a text button
+    RequestContact {
+        request_contact: bool,
+    },
+    /// only for reply keyboard, sends user's location, `false` is a text button
+    RequestLocation {
+        request_location: bool,
+    },
+    /// only for reply keyboard, sends button's name as a message, set as `text: true`
+    Text {
+        #[serde(deserialize_with = "only_true")]
+        text: bool,
+    },
+}
+
+fn only_true<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match bool::deserialize(deserializer)? {
+        true => Ok(true),
+        false => Err(D::Error::custom("`text` of button can only be true")),
+    }
 }
 
 impl ButtonRaw {
@@ -31,7 +85,10 @@ impl ButtonRaw {
             name: ButtonName::Literal {
                 literal: literal.clone(),
             },
-            callback_name: literal,
+            kind: ButtonKind::Callback {
+                callback_name: literal,
+                data: None,
+            },
         }
     }
 
@@ -39,8 +96,8 @@ impl ButtonRaw {
         &self.name
     }
 
-    pub fn callback_name(&self) -> &str {
-        &self.callback_name
+    pub fn kind(&self) -> &ButtonKind {
+        &self.kind
     }
 
     pub fn literal(&self) -> Option<String> {
@@ -59,22 +116,41 @@ pub enum ButtonName {
 }
 
 impl ButtonName {
-    pub async fn resolve_name(self, db: &mut DB) -> ConfigResult<String> {
+    pub async fn resolve_name(self, db: &mut DB, langs: &Langs) -> ConfigResult<String> {
         match self {
             ButtonName::Value { name } => Ok(name),
-            ButtonName::Literal { literal } => {
-                let value = db.get_literal_value(&literal).await?;
-
-                Ok(match value {
-                    Some(value) => Ok(value),
-                    None => {
-                        notify_admin(&format!("Literal `{literal}` is not set!!!")).await;
-                        Err(ConfigError::Other(format!(
-                            "not found literal `{literal}` in DB"
-                        )))
-                    }
-                }?)
-            }
+            ButtonName::Literal { literal } => resolve_literal(&literal, db, langs).await,
+        }
+    }
+}
+
+/// Value of button's field, set as is or taken from literal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ButtonValue {
+    Value(String),
+    Literal { literal: String },
+}
+
+impl ButtonValue {
+    pub async fn resolve(self, db: &mut DB, langs: &Langs) -> ConfigResult<String> {
+        match self {
+            ButtonValue::Value(value) => Ok(value),
+            ButtonValue::Literal { literal } => resolve_literal(&literal, db, langs).await,
+        }
+    }
+}
+
+async fn resolve_literal(literal: &str, db: &mut DB, langs: &Langs) -> ConfigResult<String> {
+    let value = db.get_literal_value_lang(literal, langs).await?;
+
+    match value {
+        Some(value) => Ok(value),
+        None => {
+            notify_admin(&format!("Literal `{literal}` is not set!!!")).await;
+            Err(ConfigError::Other(format!(
+                "not found literal `{literal}` in DB"
+            )))
         }
     }
 }
@@ -84,36 +160,161 @@ pub enum ButtonLayout {
         name: String,
         literal: Option<String>,
         callback: String,
+        data: Option<Value>,
+    },
+    Url {
+        name: String,
+        url: String,
+    },
+    WebApp {
+        name: String,
+        url: String,
+    },
+    SwitchInline {
+        name: String,
+        query: String,
+        current_chat: bool,
+    },
+    LoginUrl {
+        name: String,
+        url: String,
+    },
+    CopyText {
+        name: String,
+        text: String,
+    },
+    RequestContact {
+        name: String,
+    },
+    RequestLocation {
+        name: String,
+    },
+    Text {
+        name: String,
     },
 }
 
 impl ButtonLayout {
-    pub async fn resolve_raw(braw: ButtonRaw, db: &mut DB) -> ConfigResult<Self> {
-        let name = braw.name().clone().resolve_name(db).await?;
+    pub async fn resolve_raw(braw: ButtonRaw, db: &mut DB, langs: &Langs) -> ConfigResult<Self> {
         let literal = braw.literal();
-        let callback = braw.callback_name().to_string();
-        Ok(Self::Callback {
-            name,
-            literal,
-            callback,
-        })
+        let name = braw.name.resolve_name(db, langs).await?;
+        let layout = match braw.kind {
+            ButtonKind::Callback {
+                callback_name,
+                data,
+            } => Self::Callback {
+                name,
+                literal,
+                callback: callback_name,
+                data,
+            },
+            ButtonKind::Url { url } => Self::Url {
+                name,
+                url: url.resolve(db, langs).await?,
+            },
+            ButtonKind::WebApp { web_app } => Self::WebApp {
+                name,
+                url: web_app.resolve(db, langs).await?,
+            },
+            ButtonKind::SwitchInline {
+                switch_inline,
+                current_chat,
+            } => Self::SwitchInline {
+                name,
+                query: switch_inline.resolve(db, langs).await?,
+                current_chat,
+            },
+            ButtonKind::LoginUrl { login_url } => Self::LoginUrl {
+                name,
+                url: login_url.resolve(db, langs).await?,
+            },
+            ButtonKind::CopyText { copy_text } => Self::CopyText {
+                name,
+                text: copy_text.resolve(db, langs).await?,
+            },
+            ButtonKind::RequestContact {
+                request_contact: true,
+            } => Self::RequestContact { name },
+            ButtonKind::RequestLocation {
+                request_location: true,
+            } => Self::RequestLocation { name },
+            ButtonKind::RequestContact { .. }
+            | ButtonKind::RequestLocation { .. }
+            | ButtonKind::Text { .. } => Self::Text { name },
+        };
+
+        Ok(layout)
     }
 }
 
+#[async_trait]
 impl<P: Provider> ResolveValue for ButtonDefinition<P> {
     type Value = ButtonRaw;
     type Runtime = P;
 
-    fn resolve(self) -> ConfigResult<Self::Value> {
+    async fn resolve(self) -> ConfigResult<Self::Value> {
         match self {
             ButtonDefinition::Button(button) => Ok(button),
             ButtonDefinition::ButtonLiteral(l) => Ok(ButtonRaw::from_literal(l)),
-            ButtonDefinition::Function(f) => <Self as ResolveValue>::resolve(match f.call()? {
-                Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
-                None => Err(ConfigError::Other(
-                    "Function didn't return value".to_string(),
-                )),
-            }?),
+            ButtonDefinition::Function(f) => {
+                <Self as ResolveValue>::resolve(match f.call_async().await? {
+                    Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
+                    None => Err(ConfigError::Other(
+                        "Function didn't return value".to_string(),
+                    )),
+                }?)
+                .await
+            }
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_button_kinds() {
+        let buttons: Vec<ButtonRaw> = serde_json::from_value(json!([
+            {"name": {"name": "go"}, "callback_name": "product", "data": {"id": 1}},
+            {"name": {"literal": "site_btn"}, "url": {"literal": "site_url"}},
+            {"name": {"name": "app"}, "web_app": "https://example.com/app"},
+            {"name": {"name": "share"}, "switch_inline": "promo", "current_chat": true},
+            {"name": {"name": "copy"}, "copy_text": "PROMO10"},
+            {"name": {"name": "phone"}, "request_contact": true},
+            {"name": {"name": "yes"}, "text": true},
+        ]))
+        .unwrap();
+
+        assert!(matches!(
+            buttons[0].kind(),
+            ButtonKind::Callback { data: Some(_), .. }
+        ));
+        assert!(matches!(buttons[1].kind(), ButtonKind::Url { .. }));
+        assert!(matches!(buttons[2].kind(), ButtonKind::WebApp { .. }));
+        assert!(matches!(
+            buttons[3].kind(),
+            ButtonKind::SwitchInline {
+                current_chat: true,
+                ..
+            }
+        ));
+        assert!(matches!(buttons[4].kind(), ButtonKind::CopyText { .. }));
+        assert!(matches!(
+            buttons[5].kind(),
+            ButtonKind::RequestContact { .. }
+        ));
+        assert!(matches!(buttons[6].kind(), ButtonKind::Text { .. }));
+
+        // misspelled field is not taken for a text button
+        let button: Result<ButtonRaw, _> =
+            serde_json::from_value(json!({"name": {"name": "go"}, "callback_nme": "product"}));
+        assert!(button.is_err());
+        let button: Result<ButtonRaw, _> =
+            serde_json::from_value(json!({"name": {"name": "no"}, "text": false}));
+        assert!(button.is_err());
+    }
+}
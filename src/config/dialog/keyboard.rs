@@ -1,3 +1,5 @@
+use async_trait::async_trait;
+use futures::future::try_join_all;
 use serde::{Deserialize, Serialize};
 
 use crate::config::{
@@ -23,36 +25,48 @@ pub enum RowDefinition<P: Provider> {
     Function(BotFunction<P>),
 }
 
+#[async_trait]
 impl<P: Provider> ResolveValue for KeyboardDefinition<P> {
     type Value = Vec<<RowDefinition<P> as ResolveValue>::Value>;
     type Runtime = P;
 
-    fn resolve(self) -> ConfigResult<Self::Value> {
+    async fn resolve(self) -> ConfigResult<Self::Value> {
         match self {
-            KeyboardDefinition::Rows(rows) => rows.into_iter().map(|r| r.resolve()).collect(),
-            KeyboardDefinition::Function(f) => <Self as ResolveValue>::resolve(match f.call()? {
-                Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
-                None => Err(ConfigError::Other(
-                    "Function didn't return value".to_string(),
-                )),
-            }?),
+            KeyboardDefinition::Rows(rows) => {
+                try_join_all(rows.into_iter().map(|r| r.resolve())).await
+            }
+            KeyboardDefinition::Function(f) => {
+                <Self as ResolveValue>::resolve(match f.call_async().await? {
+                    Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
+                    None => Err(ConfigError::Other(
+                        "Function didn't return value".to_string(),
+                    )),
+                }?)
+                .await
+            }
         }
     }
 }
 
+#[async_trait]
 impl<P: Provider> ResolveValue for RowDefinition<P> {
     type Value = Vec<<ButtonDefinition<P> as ResolveValue>::Value>;
     type Runtime = P;
 
-    fn resolve(self) -> ConfigResult<Self::Value> {
+    async fn resolve(self) -> ConfigResult<Self::Value> {
         match self {
-            RowDefinition::Buttons(buttons) => buttons.into_iter().map(|b| b.resolve()).collect(),
-            RowDefinition::Function(f) => <Self as ResolveValue>::resolve(match f.call()? {
-                Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
-                None => Err(ConfigError::Other(
-                    "Function didn't return value".to_string(),
-                )),
-            }?),
+            RowDefinition::Buttons(buttons) => {
+                try_join_all(buttons.into_iter().map(|b| b.resolve())).await
+            }
+            RowDefinition::Function(f) => {
+                <Self as ResolveValue>::resolve(match f.call_async().await? {
+                    Some(t) => Ok(t.de_into().map_err(ConfigError::as_provider_err)?),
+                    None => Err(ConfigError::Other(
+                        "Function didn't return value".to_string(),
+                    )),
+                }?)
+                .await
+            }
         }
     }
 }
@@ -1,9 +1,12 @@
+use std::collections::HashMap;
+
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::{
     config::{function::BotFunction, result::ConfigResult, traits::ResolveValue, Provider},
-    db::DB,
+    db::{CallDB, Langs, DB},
 };
 
 use super::{button::ButtonLayout, keyboard::KeyboardDefinition};
@@ -15,12 +18,20 @@ pub struct BotMessage<P: Provider> {
     #[serde(default)]
     replace: bool,
     buttons: Option<KeyboardDefinition<P>>,
+    #[serde(default)]
+    keyboard_type: KeyboardType,
     state: Option<String>,
 
     /// flag options to command is meta, so it will be appended to user.metas in db
     meta: Option<bool>,
 
     handler: Option<BotFunction<P>>,
+
+    /// popup shown on click of button, that leads to this message
+    toast: Option<Toast>,
+    /// on button's click only toast is shown, message is not sent
+    #[serde(default)]
+    toast_only: bool,
 }
 
 impl<P: Provider> BotMessage<P> {
@@ -56,23 +67,142 @@ impl<P: Provider> BotMessage<P> {
         self.handler.as_ref()
     }
 
+    /// state user gets after this message
+    pub fn state(&self) -> Option<&String> {
+        self.state.as_ref()
+    }
+
     pub fn meta(&self) -> bool {
         self.meta.unwrap_or(false)
     }
+
+    pub fn keyboard_type(&self) -> KeyboardType {
+        self.keyboard_type
+    }
+
+    pub fn toast(&self) -> Option<&Toast> {
+        self.toast.as_ref()
+    }
+
+    pub fn is_toast_only(&self) -> bool {
+        self.toast_only
+    }
+}
+
+/// Popup shown to user as an answer to button's click
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Toast {
+    #[serde(flatten)]
+    content: ToastContent,
+    /// show as alert with `OK` button instead of notification on top of chat
+    #[serde(default)]
+    pub show_alert: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ToastContent {
+    Literal { literal: String },
+    Text { text: String },
+}
+
+impl Toast {
+    /// text of toast, `None` if its literal is not set
+    pub async fn resolve(&self, db: &DB, langs: &Langs) -> ConfigResult<Option<String>> {
+        match &self.content {
+            ToastContent::Literal { literal } => {
+                Ok(db.get_literal_value_lang(literal, langs).await?)
+            }
+            ToastContent::Text { text } => Ok(Some(text.clone())),
+        }
+    }
+}
+
+/// How message's buttons are shown to user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardType {
+    /// buttons attached to message
+    #[default]
+    Inline,
+    /// buttons shown instead of user's keyboard
+    Reply,
+    /// the same as `Reply`, but hidden after button is pressed
+    OneTime,
+    /// removes previously shown reply keyboard, buttons are ignored
+    Remove,
+}
+
+/// Value returned by message's handler
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HandlerReturn<P: Provider> {
+    /// whether configured message should be sent after handler
+    Propagate(bool),
+    /// the same as `Propagate`, non-zero to send message
+    Code(f64),
+    /// message to send instead of configured one
+    Message(MessageOverride<P>),
+}
+
+impl<P: Provider> HandlerReturn<P> {
+    pub fn is_propagate(&self) -> bool {
+        match self {
+            HandlerReturn::Propagate(propagate) => *propagate,
+            HandlerReturn::Code(code) => *code != 0.0,
+            HandlerReturn::Message(_) => true,
+        }
+    }
+}
+
+/// Fields of message set by handler, every field that is not set
+/// is taken from configured message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageOverride<P: Provider> {
+    pub literal: Option<String>,
+    pub variant: Option<String>,
+    pub buttons: Option<KeyboardDefinition<P>>,
+    pub keyboard_type: Option<KeyboardType>,
+    pub replace: Option<bool>,
+    pub state: Option<String>,
+    /// text sent as is instead of literal
+    pub text: Option<String>,
+    /// values of `{var.<name>}` placeholders in message's text
+    pub vars: Option<HashMap<String, Value>>,
+    pub toast: Option<Toast>,
+    pub toast_only: Option<bool>,
 }
 
 impl<P: Provider> BotMessage<P> {
+    /// message with fields replaced by set in handler's return
+    pub fn update_with(self, o: &MessageOverride<P>) -> Self {
+        BotMessage {
+            literal: o.literal.clone().or(self.literal),
+            replace: o.replace.unwrap_or(self.replace),
+            buttons: o.buttons.clone().or(self.buttons),
+            keyboard_type: o.keyboard_type.unwrap_or(self.keyboard_type),
+            state: o.state.clone().or(self.state),
+            toast: o.toast.clone().or(self.toast),
+            toast_only: o.toast_only.unwrap_or(self.toast_only),
+            ..self
+        }
+    }
+
     pub async fn resolve_buttons(
         &self,
         db: &mut DB,
+        langs: &Langs,
     ) -> ConfigResult<Option<Vec<Vec<ButtonLayout>>>> {
-        let raw_buttons = self.buttons.clone().map(|b| b.resolve()).transpose()?;
+        let raw_buttons = match self.buttons.clone() {
+            Some(buttons) => Some(buttons.resolve().await?),
+            None => None,
+        };
         match raw_buttons {
             Some(braws) => {
                 let kbd: Vec<Vec<_>> = join_all(braws.into_iter().map(|rows| async {
                     join_all(rows.into_iter().map(|b| async {
                         let mut db = db.clone();
-                        ButtonLayout::resolve_raw(b, &mut db).await
+                        ButtonLayout::resolve_raw(b, &mut db, langs).await
                     }))
                     .await
                     .into_iter()
@@ -91,3 +221,50 @@ impl<P: Provider> BotMessage<P> {
         self.literal.as_ref()
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{config::tests::test_config, runtimes::testing::TestRuntime};
+
+    use super::*;
+
+    #[test]
+    fn test_handler_return() {
+        let ret: HandlerReturn<TestRuntime> = serde_json::from_value(json!(false)).unwrap();
+        assert!(!ret.is_propagate());
+        let ret: HandlerReturn<TestRuntime> = serde_json::from_value(json!(1)).unwrap();
+        assert!(ret.is_propagate());
+
+        let ret: HandlerReturn<TestRuntime> =
+            serde_json::from_value(json!({"literal": "other", "state": "enter_name"})).unwrap();
+        let o = match ret {
+            HandlerReturn::Message(o) => o,
+            other => panic!("expected message, got: {other:?}"),
+        };
+        let bm = test_config()
+            .get_command_message("start")
+            .unwrap()
+            .update_with(&o);
+        assert_eq!(bm.literal().unwrap(), "other");
+        assert_eq!(bm.state().unwrap(), "enter_name");
+        assert!(bm.toast().is_none());
+
+        let ret: HandlerReturn<TestRuntime> = serde_json::from_value(
+            json!({"toast": {"text": "Added to cart", "show_alert": true}, "toast_only": true}),
+        )
+        .unwrap();
+        let o = match ret {
+            HandlerReturn::Message(o) => o,
+            other => panic!("expected message, got: {other:?}"),
+        };
+        let bm = test_config()
+            .get_command_message("start")
+            .unwrap()
+            .update_with(&o);
+        assert!(bm.toast().unwrap().show_alert);
+        assert!(bm.is_toast_only());
+    }
+}
@@ -13,7 +13,7 @@ use super::Provider;
 pub struct BotDialog<P: Provider> {
     pub commands: HashMap<String, BotMessage<P>>,
     pub buttons: HashMap<String, BotMessage<P>>,
-    stateful_msg_handlers: HashMap<String, BotMessage<P>>,
+    pub stateful_msg_handlers: HashMap<String, BotMessage<P>>,
     #[serde(default)]
     pub(crate) variants: HashMap<String, HashMap<String, BotMessage<P>>>,
 }
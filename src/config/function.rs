@@ -10,12 +10,14 @@ use super::{
 pub struct BotFunction<P: Provider>(P::Function);
 
 impl<P: Provider> BotFunction<P> {
-    pub fn call(&self) -> ConfigResult<Option<P::Value>> {
-        self.call_args(&[])
+    pub async fn call_async(&self) -> ConfigResult<Option<P::Value>> {
+        self.call_args_async(&[]).await
     }
 
-    pub fn call_args(&self, args: &[&P::Value]) -> ConfigResult<Option<P::Value>> {
-        let val = ProviderCall::call(&self.0, args).map_err(ConfigError::as_provider_err)?;
+    pub async fn call_args_async(&self, args: &[&P::Value]) -> ConfigResult<Option<P::Value>> {
+        let val = ProviderCall::call_async(&self.0, args)
+            .await
+            .map_err(ConfigError::as_provider_err)?;
         Ok(val)
     }
 }
@@ -8,13 +8,16 @@ pub mod time;
 
 use std::time::Duration;
 
+use crate::db::DEFAULT_CALLBACK_TTL;
 use chrono::DateTime;
+use chrono::DurationRound;
 use chrono::TimeDelta;
 use chrono::Utc;
 use dialog::message::BotMessage;
 use dialog::BotDialog;
 use itertools::Itertools;
 use notification::batch::NotificationBatch;
+use notification::relative::RelativeNotification;
 use notification::BotNotification;
 use serde::Deserialize;
 use serde::Serialize;
@@ -66,6 +69,27 @@ impl<P: Provider> RunnerConfig<P> {
         bm.map(|bm| bm.fill_literal(callback.to_string()))
     }
 
+    /// handler of user's message, when user is in script's `state`
+    pub fn get_stateful_message(&self, state: &str) -> Option<BotMessage<P>> {
+        let bm = self.dialog.stateful_msg_handlers.get(state).cloned();
+
+        bm.map(|bm| bm.fill_literal(state.to_string()))
+    }
+
+    pub fn default_language(&self) -> Option<&str> {
+        self.config.default_language.as_deref()
+    }
+
+    pub fn callback_ttl(&self) -> Duration {
+        self.config
+            .callback_ttl
+            .map_or(DEFAULT_CALLBACK_TTL, Duration::from_secs)
+    }
+
+    pub fn expired_callback(&self) -> &ExpiredCallback {
+        &self.config.expired_callback
+    }
+
     pub fn created_at(&self) -> DateTime<Utc> {
         self.timezoned_time(self.created_at.at)
     }
@@ -79,28 +103,53 @@ impl<P: Provider> RunnerConfig<P> {
     /// so, if you'll get None, no notifications will be provided later
     pub fn get_nearest_notifications(&self) -> Option<NotificationBatch<P>> {
         let start_time = self.created_at();
-        let now = self.timezoned_time(chrono::offset::Utc::now());
+        let utc_now = chrono::offset::Utc::now();
+        let now = self.timezoned_time(utc_now);
 
         let ordered = self
             .notifications
             .iter()
-            .filter(|f| f.left_time(start_time, now) > Duration::from_secs(1))
-            .sorted_by_key(|f| f.left_time(start_time, now))
+            .enumerate()
+            .filter(|(_, f)| !f.is_relative())
+            // schedules, that never match, are skipped
+            .filter_map(|(i, f)| Some((i, f, f.left_time(start_time, now)?)))
+            .filter(|(_, _, left)| *left > Duration::from_secs(1))
+            .sorted_by_key(|(_, _, left)| *left)
             .collect::<Vec<_>>();
 
         let left = match ordered.first() {
-            Some(notification) => notification.left_time(start_time, now),
+            Some((_, _, left)) => *left,
             // No notifications provided
             None => return None,
         };
         // get all that should be sent at the same time
         let notifications = ordered
             .into_iter()
-            .filter(|n| n.left_time(start_time, now) == left)
-            .cloned()
+            .filter(|(_, _, l)| *l == left)
+            .map(|(i, n, _)| (n.name(i), n.clone()))
             .collect::<Vec<_>>();
 
-        Some(NotificationBatch::new(left, notifications))
+        let at = utc_now + left;
+        let at = at.duration_trunc(TimeDelta::minutes(1)).unwrap_or(at);
+
+        Some(NotificationBatch::new(left, at, notifications))
+    }
+
+    /// notifications, which timers start from users' events
+    pub fn relative_notifications(&self) -> Vec<RelativeNotification> {
+        self.notifications
+            .iter()
+            .enumerate()
+            .filter_map(|(i, n)| n.relative(i))
+            .collect()
+    }
+
+    pub fn get_notification(&self, name: &str) -> Option<BotNotification<P>> {
+        self.notifications
+            .iter()
+            .enumerate()
+            .find(|(i, n)| n.name(*i) == name)
+            .map(|(_, n)| n.clone())
     }
 }
 
@@ -125,4 +174,115 @@ pub struct BotConfig {
     /// timezone =-2 will be UTC-2,
     #[serde(default)]
     timezone: i8,
+    /// language of literals for users, whose language has no values set
+    default_language: Option<String>,
+    /// seconds after which buttons of sent messages stop working
+    callback_ttl: Option<u64>,
+    /// what to do when user clicks button, that stopped working
+    #[serde(default)]
+    expired_callback: ExpiredCallback,
+}
+
+/// Answer to click on button, which callback has expired or is unknown
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiredCallback {
+    /// do nothing, telegram will show loading on button for a while
+    #[default]
+    Ignore,
+    /// show popup with value of literal
+    Alert(String),
+    /// send `start` command's message
+    Start,
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        config::traits::{ProviderDeserialize, ProviderSerialize},
+        runtimes::testing::{TestInit, TestRuntime, TestValue},
+    };
+
+    use super::*;
+
+    pub(crate) fn test_config() -> RunnerConfig<TestRuntime> {
+        let config = json!({
+            "config": {"version": 1.0},
+            "dialog": {
+                "commands": {
+                    "start": {"handler": {"$function": "config_test_handler"}},
+                },
+                "buttons": {},
+                "stateful_msg_handlers": {},
+                "variants": {
+                    "start": {"promo": {"literal": "start_promo"}},
+                },
+            },
+        });
+        TestRuntime
+            .init_config(TestInit::from(config.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expired_callback() {
+        let rc = test_config();
+        assert_eq!(rc.expired_callback(), &ExpiredCallback::Ignore);
+        assert_eq!(rc.callback_ttl(), DEFAULT_CALLBACK_TTL);
+
+        let config: BotConfig = serde_json::from_value(json!({
+            "version": 1.0,
+            "callback_ttl": 3600,
+            "expired_callback": {"alert": "button_expired"},
+        }))
+        .unwrap();
+        assert_eq!(config.callback_ttl, Some(3600));
+        assert_eq!(
+            config.expired_callback,
+            ExpiredCallback::Alert("button_expired".to_string())
+        );
+        let config: BotConfig =
+            serde_json::from_value(json!({"version": 1.0, "expired_callback": "start"})).unwrap();
+        assert_eq!(config.expired_callback, ExpiredCallback::Start);
+    }
+
+    #[test]
+    fn test_command_message_varianted() {
+        let rc = test_config();
+
+        let bm = rc.get_command_message_varianted("start", "promo").unwrap();
+        assert_eq!(bm.literal().unwrap(), "start_promo");
+        // `start` is meta by default
+        assert!(bm.meta());
+
+        let bm = rc
+            .get_command_message_varianted("start", "unknown")
+            .unwrap();
+        assert_eq!(bm.literal().unwrap(), "start");
+
+        assert!(rc
+            .get_command_message_varianted("unknown", "promo")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_command_handler() {
+        TestRuntime::register("config_test_handler", |args| args.first().cloned());
+        let rc = test_config();
+
+        let bm = rc.get_command_message("start").unwrap();
+        let handler = bm.get_handler().unwrap();
+        let arg = TestValue::se_from(&"hello").unwrap();
+        let res: String = handler
+            .call_args_async(&[&arg])
+            .await
+            .unwrap()
+            .unwrap()
+            .de_into()
+            .unwrap();
+        assert_eq!(res, "hello");
+    }
 }
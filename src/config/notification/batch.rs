@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
+
 use crate::config::Provider;
 
 use super::BotNotification;
@@ -7,13 +9,20 @@ use super::BotNotification;
 #[derive(Debug, Clone)]
 pub struct NotificationBatch<P: Provider> {
     wait_for: Duration,
-    notifications: Vec<BotNotification<P>>,
+    at: DateTime<Utc>,
+    /// notifications with their names
+    notifications: Vec<(String, BotNotification<P>)>,
 }
 
 impl<P: Provider> NotificationBatch<P> {
-    pub fn new(wait_for: Duration, notifications: Vec<BotNotification<P>>) -> Self {
+    pub fn new(
+        wait_for: Duration,
+        at: DateTime<Utc>,
+        notifications: Vec<(String, BotNotification<P>)>,
+    ) -> Self {
         Self {
             wait_for,
+            at,
             notifications,
         }
     }
@@ -22,7 +31,12 @@ impl<P: Provider> NotificationBatch<P> {
         self.wait_for
     }
 
-    pub fn notifications(&self) -> &[BotNotification<P>] {
+    /// time of sending in UTC, truncated to minutes, so it is the same for every call
+    pub fn at(&self) -> DateTime<Utc> {
+        self.at
+    }
+
+    pub fn notifications(&self) -> &[(String, BotNotification<P>)] {
         &self.notifications
     }
 }
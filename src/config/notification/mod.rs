@@ -8,15 +8,19 @@ use crate::{
         result::ConfigError,
         traits::{ProviderDeserialize, ProviderSerialize},
     },
-    db::{CallDB, User, DB},
+    db::{CallDB, Langs, User, DB},
 };
 
 use super::{function::BotFunction, result::ConfigResult, time::NotificationTime, Provider};
+use relative::RelativeNotification;
 
 pub mod batch;
+pub mod relative;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BotNotification<P: Provider> {
+    /// identifies notifications scheduled for users, index in config is used if not set
+    name: Option<String>,
     time: NotificationTime,
     #[serde(default)]
     filter: NotificationFilter<P>,
@@ -24,21 +28,52 @@ pub struct BotNotification<P: Provider> {
 }
 
 impl<P: Provider> BotNotification<P> {
-    pub fn left_time(&self, start_time: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
-        let next = self.time.when_next(start_time, now);
+    /// `None` if notification is never sent to all users at once
+    pub fn left_time(&self, start_time: DateTime<Utc>, now: DateTime<Utc>) -> Option<Duration> {
+        let next = self.time.when_next(start_time, now)?;
 
         // immidate notification if time to do it passed
         let duration = (next - now).to_std().unwrap_or(Duration::from_secs(0));
 
         // Rounding partitions of seconds
-        Duration::from_secs(duration.as_secs())
+        Some(Duration::from_secs(duration.as_secs()))
+    }
+
+    /// name of notification, `index` is its position in config
+    pub fn name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| index.to_string())
+    }
+
+    /// is sent once after bot's start, so its time moves on restart
+    pub fn is_once_after_start(&self) -> bool {
+        self.time.is_once_after_start()
+    }
+
+    /// is sent to each user after user's event, `filter` is not used then
+    pub fn is_relative(&self) -> bool {
+        self.time.after().is_some()
+    }
+
+    pub fn relative(&self, index: usize) -> Option<RelativeNotification> {
+        self.time
+            .after()
+            .map(|(after, delay)| RelativeNotification {
+                name: self.name(index),
+                after: after.clone(),
+                delay,
+            })
     }
 
     pub async fn get_users(&self, db: &DB) -> ConfigResult<Vec<User>> {
         self.filter.get_users(db).await
     }
-    pub async fn resolve_message(&self, db: &DB, user: &User) -> ConfigResult<Option<String>> {
-        self.message.resolve(db, user).await
+    pub async fn resolve_message(
+        &self,
+        db: &DB,
+        user: &User,
+        langs: &Langs,
+    ) -> ConfigResult<Option<String>> {
+        self.message.resolve(db, user, langs).await
     }
 }
 
@@ -57,10 +92,10 @@ pub enum NotificationFilter<P: Provider> {
 impl<P: Provider> NotificationFilter<P> {
     pub async fn get_users(&self, db: &DB) -> ConfigResult<Vec<User>> {
         match self {
-            NotificationFilter::All => Ok(db.get_users().await?),
+            NotificationFilter::All => Ok(db.get_active_users().await?),
             NotificationFilter::Random { random } => Ok(db.get_random_users(*random).await?),
             NotificationFilter::BotFunction(f) => {
-                let uids = match f.call()? {
+                let uids = match f.call_async().await? {
                     Some(t) => Ok(t),
                     None => Err(ConfigError::Other(
                         "Function didn't return value".to_string(),
@@ -89,14 +124,21 @@ pub enum NotificationMessage<P: Provider> {
 }
 
 impl<P: Provider> NotificationMessage<P> {
-    pub async fn resolve(&self, db: &DB, user: &User) -> ConfigResult<Option<String>> {
+    pub async fn resolve(
+        &self,
+        db: &DB,
+        user: &User,
+        langs: &Langs,
+    ) -> ConfigResult<Option<String>> {
         match self {
-            NotificationMessage::Literal { literal } => Ok(db.get_literal_value(literal).await?),
+            NotificationMessage::Literal { literal } => {
+                Ok(db.get_literal_value_lang(literal, langs).await?)
+            }
             NotificationMessage::Text { text } => Ok(Some(text.to_string())),
             NotificationMessage::BotFunction(f) => {
                 let puser = <P::Value as ProviderSerialize>::se_from(user)
                     .map_err(ConfigError::as_provider_err)?;
-                let text = match f.call_args(&[&puser])? {
+                let text = match f.call_args_async(&[&puser]).await? {
                     Some(t) => t.de_into().map_err(ConfigError::as_provider_err)?,
                     None => None,
                 };
@@ -105,3 +147,232 @@ impl<P: Provider> NotificationMessage<P> {
         }
     }
 }
+
+#[cfg(test)]
+// allowing this since it is better for debugging tests)
+#[allow(clippy::unwrap_used)]
+#[allow(clippy::print_stdout)]
+mod tests {
+    use chrono::{TimeDelta, Timelike};
+    use serde_json::json;
+
+    use super::relative::UserEvent;
+    use crate::{config::time::SpecificTime, runtimes::testing::TestRuntime};
+
+    use super::*;
+
+    #[test]
+    fn test_notification_struct() {
+        let botn = json!({
+            "time": "18:00",
+            "filter": {"random": 2},
+            "message": {"text": "some"},
+        });
+        let n: BotNotification<TestRuntime> = serde_json::from_value(botn).unwrap();
+        println!("BotNotification: {n:#?}");
+        assert!(matches!(n.time, NotificationTime::Specific(..)));
+        let time = if let NotificationTime::Specific(st) = n.time {
+            st
+        } else {
+            unreachable!()
+        };
+        assert_eq!(time, SpecificTime::new(18, 00));
+    }
+
+    #[test]
+    fn test_notification_time() {
+        let botn = json!({
+            "time": "18:00",
+            "filter": {"random": 2},
+            "message": {"text": "some"},
+        });
+        let n: BotNotification<TestRuntime> = serde_json::from_value(botn).unwrap();
+        println!("BotNotification: {n:#?}");
+        let start_time = chrono::offset::Utc::now();
+        // let start_time = chrono::offset::Utc::now() + TimeDelta::try_hours(5).unwrap();
+        let start_time = start_time.with_hour(13).unwrap().with_minute(23).unwrap();
+        let left = n.left_time(start_time, start_time).unwrap();
+        let secs = left.as_secs();
+        let minutes = secs / 60;
+        let hours = minutes / 60;
+        let minutes = minutes % 60;
+        println!("Left: {hours}:{minutes}");
+
+        let when_should = chrono::offset::Utc::now()
+            .with_hour(18)
+            .unwrap()
+            .with_minute(00)
+            .unwrap();
+
+        let should_left = (when_should - start_time).to_std().unwrap();
+        let should_left = Duration::from_secs(should_left.as_secs());
+
+        assert_eq!(left, should_left)
+    }
+
+    #[test]
+    fn test_notification_time_nextday() {
+        let botn = json!({
+            "time": "11:00",
+            "filter": {"random": 2},
+            "message": {"text": "some"},
+        });
+        let n: BotNotification<TestRuntime> = serde_json::from_value(botn).unwrap();
+        println!("BotNotification: {n:#?}");
+        let start_time = chrono::offset::Utc::now();
+        // let start_time = chrono::offset::Utc::now() + TimeDelta::try_hours(5).unwrap();
+        let start_time = start_time.with_hour(13).unwrap().with_minute(23).unwrap();
+        let left = n.left_time(start_time, start_time).unwrap();
+        let secs = left.as_secs();
+        let minutes = secs / 60;
+        let hours = minutes / 60;
+        let minutes = minutes % 60;
+        println!("Left: {hours}:{minutes}");
+
+        let when_should = chrono::offset::Utc::now()
+            .with_hour(11)
+            .unwrap()
+            .with_minute(00)
+            .unwrap();
+
+        let should_left = (when_should + TimeDelta::days(1) - start_time)
+            .to_std()
+            .unwrap();
+        let should_left = Duration::from_secs(should_left.as_secs());
+
+        assert_eq!(left, should_left)
+    }
+
+    /// 2025-06-02 is a monday
+    fn datetime(s: &str) -> DateTime<Utc> {
+        chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn notification(time: serde_json::Value) -> BotNotification<TestRuntime> {
+        let botn = json!({
+            "time": time,
+            "message": {"text": "some"},
+        });
+        serde_json::from_value(botn).unwrap()
+    }
+
+    #[test]
+    fn test_notification_time_once() {
+        let start_time = datetime("2025-06-02 13:23");
+
+        let n = notification(json!({"once": "2025-06-03 10:00"}));
+        let left = n.left_time(start_time, start_time).unwrap();
+        assert_eq!(left, Duration::from_secs((20 * 60 + 37) * 60));
+        assert!(!n.is_once_after_start());
+
+        let n = notification(json!({"once": "17:49"}));
+        let left = n.left_time(start_time, start_time).unwrap();
+        assert_eq!(left, Duration::from_secs((4 * 60 + 26) * 60));
+        assert!(n.is_once_after_start());
+        // only once, even days later
+        let now = datetime("2025-06-05 12:00");
+        assert_eq!(n.left_time(start_time, now), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_notification_time_weekdays() {
+        let n = notification(json!({"at": "10:00", "weekdays": ["Mon", "Wed"]}));
+        let now = datetime("2025-06-02 13:23");
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-06-04 10:00"))
+        );
+
+        let n = notification(json!({"at": "10:00", "month_days": [1, 15]}));
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-06-15 10:00"))
+        );
+        let now = datetime("2025-06-15 10:00");
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-07-01 10:00"))
+        );
+    }
+
+    #[test]
+    fn test_notification_time_cron() {
+        let now = datetime("2025-06-02 13:23");
+
+        // every 15 minutes
+        let n = notification(json!({"cron": "*/15 * * * *"}));
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-06-02 13:30"))
+        );
+
+        // on working days at 9:30
+        let n = notification(json!({"cron": "30 9 * * 1-5"}));
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-06-03 09:30"))
+        );
+        let friday = datetime("2025-06-06 12:00");
+        assert_eq!(
+            n.time.when_next(friday, friday),
+            Some(datetime("2025-06-09 09:30"))
+        );
+
+        // on 1st day of month or on sundays (7 is also sunday)
+        let n = notification(json!({"cron": "0 12 1 * 7"}));
+        assert_eq!(
+            n.time.when_next(now, now),
+            Some(datetime("2025-06-08 12:00"))
+        );
+
+        let n: Result<BotNotification<TestRuntime>, _> = serde_json::from_value(json!({
+            "time": {"cron": "60 * * * *"},
+            "message": {"text": "some"},
+        }));
+        assert!(n.is_err());
+
+        // 30th of february never comes
+        let n = notification(json!({"cron": "0 0 30 2 *"}));
+        assert_eq!(n.time.when_next(now, now), None);
+        assert_eq!(n.left_time(now, now), None);
+    }
+
+    #[test]
+    fn test_notification_time_empty_days() {
+        for time in [
+            json!({"at": "10:00", "weekdays": []}),
+            json!({"at": "10:00", "month_days": []}),
+        ] {
+            let n: Result<BotNotification<TestRuntime>, _> = serde_json::from_value(json!({
+                "time": time,
+                "message": {"text": "some"},
+            }));
+            assert!(n.is_err());
+        }
+    }
+
+    #[test]
+    fn test_notification_relative() {
+        let n: BotNotification<TestRuntime> = serde_json::from_value(json!({
+            "name": "onboarding",
+            "time": {"after": "first_seen", "delta_hours": 2},
+            "message": {"literal": "onboarding_msg"},
+        }))
+        .unwrap();
+        let rn = n.relative(0).unwrap();
+        assert_eq!(rn.name, "onboarding");
+        assert_eq!(rn.after, UserEvent::FirstSeen);
+        assert_eq!(rn.delay, Duration::from_secs(2 * 60 * 60));
+
+        let n = notification(json!({"after": {"callback": "buy"}, "delta_minutes": 30}));
+        let rn = n.relative(3).unwrap();
+        assert_eq!(rn.name, "3");
+        assert_eq!(rn.after, UserEvent::Callback("buy".to_string()));
+        assert_eq!(rn.delay, Duration::from_secs(30 * 60));
+
+        let n = notification(json!({"delta_minutes": 30}));
+        assert!(!n.is_relative());
+    }
+}
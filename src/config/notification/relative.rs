@@ -0,0 +1,88 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{scheduled_message::ScheduledMessage, CallDB, DbResult};
+
+/// Event of user, after which notification's timer starts.
+/// Each notification is scheduled only once per user, so repeated events,
+/// e.g. clicks on the same button, don't start it again
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserEvent {
+    /// user has interacted with bot for the first time
+    FirstSeen,
+    /// meta was inserted to user, e.g. by `/start promo`
+    Meta(String),
+    /// user has clicked button with callback
+    Callback(String),
+    /// user has left application
+    Application,
+}
+
+/// Notification, which is sent to each user after user's event
+#[derive(Debug, Clone)]
+pub struct RelativeNotification {
+    /// name of notification in bot's config
+    pub name: String,
+    pub after: UserEvent,
+    pub delay: Duration,
+}
+
+/// Relative notifications of bot, shared with script's host functions,
+/// since notifications are known only after script is initialized
+#[derive(Debug, Clone, Default)]
+pub struct RelativeNotifications(Arc<RwLock<Vec<RelativeNotification>>>);
+
+impl From<Vec<RelativeNotification>> for RelativeNotifications {
+    fn from(value: Vec<RelativeNotification>) -> Self {
+        Self(Arc::new(RwLock::new(value)))
+    }
+}
+
+impl RelativeNotifications {
+    pub fn set(&self, notifications: Vec<RelativeNotification>) {
+        let mut n = self.0.write().expect("Poisoned notifications lock");
+        *n = notifications;
+    }
+
+    /// schedules messages of notifications, that are started by `event`
+    pub async fn schedule<D: CallDB>(
+        &self,
+        db: &mut D,
+        user_id: i64,
+        event: &UserEvent,
+    ) -> DbResult<()> {
+        self.schedule_from(db, user_id, event, Utc::now()).await
+    }
+
+    /// same as `schedule`, but event happened `at`, so timers,
+    /// that have already expired, are not started
+    pub async fn schedule_from<D: CallDB>(
+        &self,
+        db: &mut D,
+        user_id: i64,
+        event: &UserEvent,
+        at: DateTime<Utc>,
+    ) -> DbResult<()> {
+        let now = Utc::now();
+        let scheduled = {
+            let n = self.0.read().expect("Poisoned notifications lock");
+            n.iter()
+                .filter(|n| n.after == *event)
+                .map(|n| ScheduledMessage::new(user_id, n.name.clone(), at + n.delay))
+                .filter(|sm| sm.send_at >= now)
+                .collect::<Vec<_>>()
+        };
+
+        for sm in scheduled {
+            sm.store(db).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,38 +1,116 @@
 use std::time::Duration;
 
-use chrono::{DateTime, Days, NaiveTime, ParseError, TimeDelta, Timelike, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{
+    DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, ParseError, TimeDelta, Timelike,
+    Utc, Weekday,
+};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
+use super::notification::relative::UserEvent;
+
+/// how far schedules are looked up, if they never match, notification is never sent
+const MAX_LOOKUP_DAYS: u64 = 366 * 4;
+
+/// Variants with required fields go first, since `Delta` matches any map
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum NotificationTime {
-    Delta {
+    /// only once, at date and time or at time after bot start
+    Once {
+        once: OnceTime,
+    },
+    /// at time on given days of week
+    Weekdays {
+        at: SpecificTime,
+        #[serde(deserialize_with = "non_empty")]
+        weekdays: Vec<Weekday>,
+    },
+    /// at time on given days of month, e.g. `[1, 15]`
+    MonthDays {
+        at: SpecificTime,
+        #[serde(deserialize_with = "non_empty")]
+        month_days: Vec<u32>,
+    },
+    /// cron expression, e.g. `30 9 * * 1-5`, see [`CronSchedule`]
+    Cron {
+        cron: CronSchedule,
+    },
+    /// after user's event, timer starts for each user separately,
+    /// see [`RelativeNotification`](super::notification::relative::RelativeNotification)
+    After {
+        after: UserEvent,
         #[serde(default)]
         delta_hours: u32,
         #[serde(default)]
         delta_minutes: u32,
     },
+    Delta(DeltaTime),
     Specific(SpecificTime),
 }
 
+/// Period of notification, unknown fields are not allowed,
+/// so invalid variants above are not taken for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeltaTime {
+    #[serde(default)]
+    delta_hours: u32,
+    #[serde(default)]
+    delta_minutes: u32,
+}
+
+/// schedule with no days would never be sent
+fn non_empty<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let values = Vec::<T>::deserialize(deserializer)?;
+    if values.is_empty() {
+        return Err(D::Error::custom("list of days should not be empty"));
+    }
+
+    Ok(values)
+}
+
 impl NotificationTime {
-    pub fn when_next(&self, start_time: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+    /// `None` if notification is not sent to all users, or its schedule never matches
+    pub fn when_next(
+        &self,
+        start_time: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
         match self {
-            NotificationTime::Delta {
+            NotificationTime::Once {
+                once: OnceTime::At(at),
+            } => Some(at.and_utc()),
+            NotificationTime::Once {
+                once: OnceTime::AfterStart(time),
+            } => next_time(start_time, time, |_| true),
+            NotificationTime::Weekdays { at, weekdays } => {
+                next_time(now, at, |date| weekdays.contains(&date.weekday()))
+            }
+            NotificationTime::MonthDays { at, month_days } => {
+                next_time(now, at, |date| month_days.contains(&date.day()))
+            }
+            NotificationTime::Cron { cron } => cron.when_next(now),
+            // not sent to all users at once, scheduled per user instead
+            NotificationTime::After { .. } => None,
+            NotificationTime::Delta(DeltaTime {
                 delta_hours,
                 delta_minutes,
-            } => {
+            }) => {
                 let delta = TimeDelta::minutes((delta_minutes + delta_hours * 60).into());
 
                 let secs_period = delta.num_seconds();
                 if secs_period == 0 {
-                    return now;
+                    return Some(now);
                 };
 
                 let diff = now - start_time;
                 let passed = diff.num_seconds().abs() % secs_period;
 
-                now - Duration::from_secs(passed as u64) + delta
+                Some(now - Duration::from_secs(passed as u64) + delta)
             }
             NotificationTime::Specific(time) => {
                 let estimation = now;
@@ -42,13 +120,84 @@ impl NotificationTime {
                     .unwrap_or(estimation);
 
                 if estimation < now {
-                    estimation + Days::new(1)
+                    Some(estimation + Days::new(1))
                 } else {
-                    estimation
+                    Some(estimation)
                 }
             }
         }
     }
+
+    /// sent once at time relative to bot's start
+    pub fn is_once_after_start(&self) -> bool {
+        matches!(
+            self,
+            NotificationTime::Once {
+                once: OnceTime::AfterStart(_)
+            }
+        )
+    }
+
+    /// event and delay after it, if time is relative to user's event
+    pub fn after(&self) -> Option<(&UserEvent, Duration)> {
+        match self {
+            NotificationTime::After {
+                after,
+                delta_hours,
+                delta_minutes,
+            } => {
+                let minutes = u64::from(*delta_minutes) + u64::from(*delta_hours) * 60;
+                Some((after, Duration::from_secs(minutes * 60)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// first time after `after` on date, that matches `is_date`
+fn next_time(
+    after: DateTime<Utc>,
+    time: &SpecificTime,
+    is_date: impl Fn(NaiveDate) -> bool,
+) -> Option<DateTime<Utc>> {
+    (0..=MAX_LOOKUP_DAYS)
+        .filter_map(|days| after.date_naive().checked_add_days(Days::new(days)))
+        .filter(|date| is_date(*date))
+        .filter_map(|date| date.and_hms_opt(time.hour.into(), time.minutes.into(), 0))
+        .map(|dt| dt.and_utc())
+        .find(|dt| *dt > after)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum OnceTime {
+    /// date and time, e.g. `2025-06-01 17:49`
+    At(NaiveDateTime),
+    /// time of the first day after bot's start, e.g. `17:49`.
+    /// Bot's start changes on restart, so its run is stored by name only
+    AfterStart(SpecificTime),
+}
+
+impl TryFrom<String> for OnceTime {
+    type Error = ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let at = NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M")
+            .or_else(|_| s.parse::<NaiveDateTime>());
+        match at {
+            Ok(at) => Ok(Self::At(at)),
+            Err(_) => SpecificTime::try_from(SpecificTimeFormat::String(s)).map(Self::AfterStart),
+        }
+    }
+}
+
+impl From<OnceTime> for String {
+    fn from(value: OnceTime) -> Self {
+        match value {
+            OnceTime::At(at) => at.format("%Y-%m-%d %H:%M").to_string(),
+            OnceTime::AfterStart(time) => format!("{:02}:{:02}", time.hour, time.minutes),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -85,3 +234,128 @@ impl TryFrom<SpecificTimeFormat> for SpecificTime {
         }
     }
 }
+
+/// Schedule in cron format: `minute hour day_of_month month day_of_week`.
+/// Each field is `*`, a value, a range `1-5`, a list `1,3,5` or any of them
+/// with a step, e.g. `*/15`. Day of week is 0-7, where both 0 and 7 are sunday.
+/// As in cron, if both days are restricted, date matching any of them is used
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    month_days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    month_days_any: bool,
+    weekdays_any: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CronError {
+    #[error("cron expression should have 5 fields, got: `{0}`")]
+    FieldsCount(String),
+    #[error("invalid field `{field}` of cron expression, expected values in {min}-{max}")]
+    InvalidField { field: String, min: u32, max: u32 },
+}
+
+/// values of cron's field in `min..=max` range
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, CronError> {
+    let invalid = || CronError::InvalidField {
+        field: field.to_string(),
+        min,
+        max,
+    };
+    let mut values = vec![];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (
+                from.parse().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let value = range.parse().map_err(|_| invalid())?;
+                (value, value)
+            }
+        };
+        if from < min || to > max || from > to || step == 0 {
+            return Err(invalid());
+        }
+        values.extend((from..=to).step_by(step));
+    }
+    values.sort();
+    values.dedup();
+
+    Ok(values)
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = CronError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, month_days, months, weekdays] = fields[..] else {
+            return Err(CronError::FieldsCount(expression));
+        };
+
+        Ok(Self {
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)?,
+            month_days: parse_cron_field(month_days, 1, 31)?,
+            months: parse_cron_field(months, 1, 12)?,
+            weekdays: parse_cron_field(weekdays, 0, 7)?
+                .into_iter()
+                // 7 is also a sunday
+                .map(|day| day % 7)
+                .collect(),
+            month_days_any: month_days == "*",
+            weekdays_any: weekdays == "*",
+            expression,
+        })
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(cron: CronSchedule) -> Self {
+        cron.expression
+    }
+}
+
+impl CronSchedule {
+    fn is_date(&self, date: NaiveDate) -> bool {
+        let month_day = self.month_days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+        let day = match (self.month_days_any, self.weekdays_any) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => month_day,
+            (false, false) => month_day || weekday,
+        };
+
+        day && self.months.contains(&date.month())
+    }
+
+    /// `None` if schedule never matches, e.g. `0 0 30 2 *`
+    pub fn when_next(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        (0..=MAX_LOOKUP_DAYS)
+            .filter_map(|days| now.date_naive().checked_add_days(Days::new(days)))
+            .filter(|date| self.is_date(*date))
+            .flat_map(|date| {
+                self.hours.iter().flat_map(move |hour| {
+                    self.minutes
+                        .iter()
+                        .filter_map(move |minute| date.and_hms_opt(*hour, *minute, 0))
+                })
+            })
+            .map(|dt| dt.and_utc())
+            .find(|dt| *dt > now)
+    }
+}
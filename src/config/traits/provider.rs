@@ -1,11 +1,13 @@
 use std::fmt::Debug;
 
-use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::error::Error;
 
+use crate::botscript::host::HostApi;
 use crate::config::RunnerConfig;
 
-pub trait Provider: Clone {
+pub trait Provider: Clone + Send + Sync + 'static {
     type Function: ProviderCall<Provider = Self>
         + Serialize
         + for<'a> Deserialize<'a>
@@ -24,12 +26,18 @@ pub trait Provider: Clone {
     type Error: Error;
 
     type InitData;
+    /// makes host functions available to script, called before [`Provider::init_config`]
+    fn attach_host(&self, host: HostApi) -> Result<(), Self::Error>;
     fn init_config(&self, d: Self::InitData) -> Result<RunnerConfig<Self>, Self::Error>;
 }
 
+#[async_trait]
 pub trait ProviderCall {
     type Provider: Provider<Function = Self>;
-    fn call(
+
+    /// calls function, waiting for result of async functions (promises, coroutines)
+    /// without blocking the caller's thread
+    async fn call_async(
         &self,
         args: &[&<Self::Provider as Provider>::Value],
     ) -> Result<Option<<Self::Provider as Provider>::Value>, <Self::Provider as Provider>::Error>;
@@ -37,8 +45,7 @@ pub trait ProviderCall {
 
 pub trait ProviderDeserialize {
     type Provider: Provider<Value = Self>;
-    // fn de_into<T: for Deserialize>(&self) -> Result<T, <Self::Provider as Provider>::Error>;
-    fn de_into<T>(&self) -> Result<T, <Self::Provider as Provider>::Error>;
+    fn de_into<T: DeserializeOwned>(&self) -> Result<T, <Self::Provider as Provider>::Error>;
 }
 
 pub trait ProviderSerialize {
@@ -1,10 +1,15 @@
+use async_trait::async_trait;
+
 use crate::config::result::ConfigResult;
 
 use super::Provider;
 
+/// value, that can be generated by script's function, which is called
+/// without blocking the caller's thread
+#[async_trait]
 pub trait ResolveValue {
     type Value;
     type Runtime: Provider;
 
-    fn resolve(self) -> ConfigResult<Self::Value>;
+    async fn resolve(self) -> ConfigResult<Self::Value>;
 }
@@ -9,12 +9,34 @@ use crate::db::GetCollection;
 use crate::query_call_consume;
 use crate::CallDB;
 
+/// Language the bot's script is written in
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptLang {
+    #[default]
+    Js,
+    Luau,
+}
+
+impl ScriptLang {
+    /// guess language by script's file name, defaulting to js
+    pub fn from_filename(filename: &str) -> Self {
+        match filename.rsplit_once('.') {
+            Some((_, "lua" | "luau")) => Self::Luau,
+            _ => Self::Js,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct BotInstance {
     pub _id: bson::oid::ObjectId,
     pub name: String,
     pub token: String,
     pub script: String,
+    /// older instances were stored without language, all of them are js
+    #[serde(default)]
+    pub lang: ScriptLang,
     pub restart_flag: bool,
     pub created_at: DateTime<FixedOffset>,
 }
@@ -30,11 +52,16 @@ impl BotInstance {
             name,
             token,
             script,
+            lang: Default::default(),
             restart_flag: false,
             created_at: Local::now().into(),
         }
     }
 
+    pub fn with_lang(self, lang: ScriptLang) -> Self {
+        Self { lang, ..self }
+    }
+
     query_call_consume!(store, self, db, Self, {
         let bi = db.get_collection::<Self>().await;
 
@@ -82,13 +109,16 @@ impl BotInstance {
         db: &mut D,
         name: &str,
         script: &str,
+        lang: ScriptLang,
     ) -> DbResult<()> {
         let bi = db.get_collection::<Self>().await;
+        let lang = bson::to_bson(&lang)?;
 
         bi.update_one(
             doc! {"name": name},
             doc! { "$set": {
                     "script": script,
+                    "lang": lang,
                     "restart_flag": true,
                 }
             },
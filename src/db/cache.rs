@@ -0,0 +1,181 @@
+//! In-process cache of literals and media, so messages with many buttons don't
+//! query db for every literal.
+//!
+//! Every write of literals or media clears the cache and increments version stored in db,
+//! other processes (and other `DB` instances) compare it with the version they've seen
+//! at most once per [`CHECK_INTERVAL`] and drop their caches if it has changed
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use mongodb::{bson::doc, options::ReturnDocument, Database};
+use serde::{Deserialize, Serialize};
+
+use super::{DbResult, Literal, LiteralAlternative, Media};
+
+/// how often version in db is checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// id of version document of literals and media
+const VERSION_ID: &str = "literals";
+
+#[derive(Serialize, Deserialize)]
+struct CacheVersion {
+    _id: String,
+    version: i64,
+}
+
+struct CheckedVersion {
+    version: i64,
+    checked_at: Option<Instant>,
+}
+
+/// key -> values in all languages or all media of literal
+type CacheMap<K, V> = RwLock<HashMap<K, Vec<V>>>;
+
+pub struct DbCache {
+    literals: CacheMap<String, Literal>,
+    alternatives: CacheMap<(String, String), LiteralAlternative>,
+    media: CacheMap<String, Media>,
+    /// incremented on every clear, so values fetched before clear are not cached
+    generation: AtomicU64,
+    version: Mutex<CheckedVersion>,
+}
+
+impl Default for DbCache {
+    fn default() -> Self {
+        Self {
+            literals: Default::default(),
+            alternatives: Default::default(),
+            media: Default::default(),
+            generation: AtomicU64::new(0),
+            version: Mutex::new(CheckedVersion {
+                version: 0,
+                checked_at: None,
+            }),
+        }
+    }
+}
+
+fn get<K: Eq + Hash, V: Clone>(map: &CacheMap<K, V>, key: &K) -> Option<Vec<V>> {
+    let map = map.read().expect("Poisoned cache lock");
+    map.get(key).cloned()
+}
+
+impl DbCache {
+    /// generation to pass to `set_*` methods, taken before value is fetched from db
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn set<K: Eq + Hash, V>(&self, map: &CacheMap<K, V>, key: K, values: Vec<V>, generation: u64) {
+        let mut map = map.write().expect("Poisoned cache lock");
+        // checking under lock, since clear takes it too
+        if self.generation() == generation {
+            map.insert(key, values);
+        }
+    }
+
+    pub fn get_literals(&self, literal: &str) -> Option<Vec<Literal>> {
+        get(&self.literals, &literal.to_string())
+    }
+
+    pub fn set_literals(&self, literal: &str, values: Vec<Literal>, generation: u64) {
+        self.set(&self.literals, literal.to_string(), values, generation)
+    }
+
+    pub fn get_alternatives(
+        &self,
+        literal: &str,
+        variant: &str,
+    ) -> Option<Vec<LiteralAlternative>> {
+        get(
+            &self.alternatives,
+            &(literal.to_string(), variant.to_string()),
+        )
+    }
+
+    pub fn set_alternatives(
+        &self,
+        literal: &str,
+        variant: &str,
+        values: Vec<LiteralAlternative>,
+        generation: u64,
+    ) {
+        let key = (literal.to_string(), variant.to_string());
+        self.set(&self.alternatives, key, values, generation)
+    }
+
+    pub fn get_media(&self, literal: &str) -> Option<Vec<Media>> {
+        get(&self.media, &literal.to_string())
+    }
+
+    pub fn set_media(&self, literal: &str, values: Vec<Media>, generation: u64) {
+        self.set(&self.media, literal.to_string(), values, generation)
+    }
+
+    fn clear(&self) {
+        let mut literals = self.literals.write().expect("Poisoned cache lock");
+        let mut alternatives = self.alternatives.write().expect("Poisoned cache lock");
+        let mut media = self.media.write().expect("Poisoned cache lock");
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        literals.clear();
+        alternatives.clear();
+        media.clear();
+    }
+
+    /// clears cache if values were changed by someone else since last check
+    pub async fn validate(&self, db: &Database) -> DbResult<()> {
+        {
+            let version = self.version.lock().expect("Poisoned cache lock");
+            if version
+                .checked_at
+                .is_some_and(|at| at.elapsed() < CHECK_INTERVAL)
+            {
+                return Ok(());
+            }
+        }
+
+        let versions = db.collection::<CacheVersion>("cache_versions");
+        let actual = versions
+            .find_one(doc! { "_id": VERSION_ID })
+            .await?
+            .map_or(0, |v| v.version);
+
+        let mut version = self.version.lock().expect("Poisoned cache lock");
+        if version.version != actual {
+            self.clear();
+            version.version = actual;
+        }
+        version.checked_at = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// clears cache and notifies other processes about changes
+    pub async fn invalidate(&self, db: &Database) -> DbResult<()> {
+        let versions = db.collection::<CacheVersion>("cache_versions");
+        let actual = versions
+            .find_one_and_update(
+                doc! { "_id": VERSION_ID },
+                doc! { "$inc": { "version": 1_i64 } },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+            .map_or(0, |v| v.version);
+
+        let mut version = self.version.lock().expect("Poisoned cache lock");
+        self.clear();
+        version.version = actual;
+        version.checked_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
@@ -2,20 +2,56 @@ use crate::query_call_consume;
 use crate::CallDB;
 use bson::oid::ObjectId;
 use chrono::DateTime;
-use chrono::FixedOffset;
-use chrono::Local;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-use super::DbResult;
+use super::{duplicate_indexes, DbResult};
 use bson::doc;
 
-#[derive(Serialize, Deserialize, Default)]
+/// FNV-1a, used since its value should not change between builds
+fn fnv1a_128(bytes: &[u8]) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013B;
+
+    bytes.iter().fold(OFFSET, |hash, byte| {
+        (hash ^ *byte as u128).wrapping_mul(PRIME)
+    })
+}
+
+/// callbacks were stored with `created_at` as a string before, which is ignored by ttl index,
+/// so they are still read, but new ones are stored as a date
+mod created_at {
+    use bson::{serde_helpers::chrono_datetime_as_bson_datetime, Bson};
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(dt: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        chrono_datetime_as_bson_datetime::serialize(dt, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        match Bson::deserialize(deserializer)? {
+            Bson::DateTime(dt) => Ok(dt.to_chrono()),
+            Bson::String(s) => DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.to_utc())
+                .map_err(D::Error::custom),
+            other => Err(D::Error::custom(format!(
+                "expected date of callback creation, got: {other}"
+            ))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct CallbackInfo<C>
 where
     C: Serialize,
 {
     pub _id: bson::oid::ObjectId,
-    pub created_at: DateTime<FixedOffset>,
+    #[serde(with = "created_at")]
+    pub created_at: DateTime<Utc>,
     pub literal: Option<String>,
     #[serde(flatten)]
     pub callback: C,
@@ -28,7 +64,7 @@ where
     pub fn new(callback: C) -> Self {
         Self {
             _id: Default::default(),
-            created_at: Local::now().into(),
+            created_at: Utc::now(),
             literal: None,
             callback,
         }
@@ -37,12 +73,26 @@ where
     pub fn new_with_literal(callback: C, literal: String) -> Self {
         Self {
             _id: Default::default(),
-            created_at: Local::now().into(),
+            created_at: Utc::now(),
             literal: Some(literal),
             callback,
         }
     }
 
+    /// id derived from literal and callback, so equal buttons share one document
+    /// instead of storing a new one for every sent message
+    pub fn with_content_id(self) -> DbResult<Self> {
+        let content = serde_json::to_vec(&(&self.literal, &self.callback))?;
+        let hash = fnv1a_128(&content).to_be_bytes();
+        let mut id = [0; 12];
+        id.copy_from_slice(&hash[..12]);
+
+        Ok(Self {
+            _id: ObjectId::from_bytes(id),
+            ..self
+        })
+    }
+
     pub fn get_id(&self) -> String {
         self._id.to_hex()
     }
@@ -56,6 +106,33 @@ where
         Ok(self)
     });
 
+    /// stores callbacks with a single request. Callbacks with content id, that are already
+    /// stored, are kept and their creation time is updated, so they don't expire
+    pub async fn store_many<D: CallDB>(db: &mut D, cis: Vec<Self>) -> DbResult<Vec<Self>> {
+        if cis.is_empty() {
+            return Ok(cis);
+        }
+        let db = db.get_database().await;
+        let ci = db.collection::<Self>("callback_info");
+
+        let duplicates = match ci.insert_many(&cis).ordered(false).await {
+            Ok(_) => vec![],
+            Err(err) => match duplicate_indexes(&err) {
+                Some(indexes) => indexes.into_iter().map(|i| cis[i]._id).collect(),
+                None => return Err(err.into()),
+            },
+        };
+        if !duplicates.is_empty() {
+            ci.update_many(
+                doc! { "_id": { "$in": duplicates } },
+                doc! { "$set": { "created_at": bson::DateTime::from_chrono(Utc::now()) } },
+            )
+            .await?;
+        }
+
+        Ok(cis)
+    }
+
     pub async fn get<D: CallDB>(db: &mut D, id: &str) -> DbResult<Option<Self>> {
         let db = db.get_database().await;
         let ci = db.collection::<Self>("callback_info");
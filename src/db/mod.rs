@@ -1,22 +1,30 @@
 pub mod application;
 pub mod bots;
+pub mod cache;
 pub mod callback_info;
 pub mod message_forward;
+pub mod notification_delivery;
 pub mod raw_calls;
+pub mod scheduled_message;
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Local, Utc};
 use enum_stringify::EnumStringify;
 use futures::stream::TryStreamExt;
+use itertools::Itertools;
 
 use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::error::{ErrorKind, InsertManyError};
 use mongodb::options::IndexOptions;
 use mongodb::{bson::doc, options::ClientOptions, Client};
 use mongodb::{Collection, Database, IndexModel};
 use serde::{Deserialize, Serialize};
 
+use cache::DbCache;
+
 #[derive(EnumStringify)]
 #[enum_stringify(case = "flat")]
 pub enum ReservationStatus {
@@ -43,6 +51,15 @@ pub struct User {
     pub username: Option<String>,
     pub language_code: Option<String>,
     pub metas: Vec<String>,
+    /// when user was seen for the first time, not set for users created before it was added
+    #[serde(default)]
+    pub created_at: Option<bson::DateTime>,
+    /// when user has blocked bot or deleted account, cleared when user writes again
+    #[serde(default)]
+    pub blocked_at: Option<bson::DateTime>,
+    /// timers of notifications after user's first interaction are started
+    #[serde(default)]
+    pub first_seen_scheduled: bool,
 }
 
 #[macro_export]
@@ -99,6 +116,21 @@ impl User {
 
         Ok(())
     }
+
+    /// marks that timers after user's first interaction are started,
+    /// returns false if they were already started by other update
+    pub async fn mark_first_seen_scheduled<D: CallDB>(&self, db: &mut D) -> DbResult<bool> {
+        let db_collection = db.get_database().await.collection::<Self>("users");
+
+        let result = db_collection
+            .update_one(
+                doc! { "_id": self._id, "first_seen_scheduled": { "$ne": true } },
+                doc! { "$set": { "first_seen_scheduled": true } },
+            )
+            .await?;
+
+        Ok(result.modified_count == 1)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -110,42 +142,144 @@ pub struct Message {
     pub variant: Option<String>,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<Utc>,
+    /// ids of all messages sent together with this one (album and its keyboard),
+    /// empty for a single message
+    #[serde(default)]
+    pub group_message_ids: Vec<i64>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Literal {
     pub _id: bson::oid::ObjectId,
     pub token: String,
+    /// language of value, `None` for value shown in any language
+    pub lang: Option<String>,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LiteralAlternative {
     pub _id: bson::oid::ObjectId,
     pub token: String,
     pub variant: String,
+    pub lang: Option<String>,
     pub value: String,
 }
 
+/// Languages to look literal up in, in order of priority. If literal is not set
+/// in any of them, value without language is used, and then value of any language
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Langs(Vec<String>);
+
+impl Langs {
+    /// chain of user's language, then bot's default one
+    pub fn new(user_lang: Option<&str>, default_lang: Option<&str>) -> Self {
+        let langs = user_lang
+            .into_iter()
+            .chain(default_lang)
+            .unique()
+            .map(str::to_string)
+            .collect();
+
+        Self(langs)
+    }
+
+    /// chain for user with `user_id`, using language of user's telegram client
+    pub async fn of_user(db: &DB, user_id: i64, default_lang: Option<&str>) -> DbResult<Self> {
+        let users = db.get_users_by_ids(vec![user_id]).await?;
+        let user_lang = users.first().and_then(|u| u.language_code.as_deref());
+
+        Ok(Self::new(user_lang, default_lang))
+    }
+
+    /// lower is better
+    fn priority(&self, lang: Option<&str>) -> usize {
+        match lang {
+            Some(lang) => match self.0.iter().position(|l| l == lang) {
+                Some(pos) => pos,
+                None => self.0.len() + 1,
+            },
+            None => self.0.len(),
+        }
+    }
+
+    /// the most suitable of values in different languages
+    pub fn choose<T>(&self, values: Vec<T>, lang: impl Fn(&T) -> Option<&str>) -> Option<T> {
+        values.into_iter().min_by_key(|v| self.priority(lang(v)))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Event {
     pub _id: bson::oid::ObjectId,
     pub time: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, EnumStringify, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[enum_stringify(case = "snake")]
+pub enum MediaType {
+    Photo,
+    Video,
+    Document,
+    /// GIF or video without sound
+    Animation,
+    Audio,
+    Voice,
+    VideoNote,
+    Sticker,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Media {
     pub _id: bson::oid::ObjectId,
     pub token: String,
-    pub media_type: String,
+    pub media_type: MediaType,
     pub file_id: String,
     pub media_group_id: Option<String>,
 }
 
+/// time after which buttons stop working, if it is not set in bot's config
+pub const DEFAULT_CALLBACK_TTL: Duration = Duration::from_secs(60 * 60 * 24 /* 1 day */);
+
+/// code of mongodb's error on creating index, that exists with other options
+const INDEX_OPTIONS_CONFLICT: i32 = 85;
+
+fn callback_ttl_index(ttl: Duration) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! {"created_at": 1})
+        .options(IndexOptions::builder().expire_after(ttl).build())
+        .build()
+}
+
+fn is_index_conflict(err: &mongodb::error::Error) -> bool {
+    matches!(*err.kind, ErrorKind::Command(ref e) if e.code == INDEX_OPTIONS_CONFLICT)
+}
+
+/// code of mongodb's error on insert of document with existing id
+const DUPLICATE_KEY: i32 = 11000;
+
+/// indexes of documents, that weren't inserted because they already exist,
+/// `None` if there were other errors
+fn duplicate_indexes(err: &mongodb::error::Error) -> Option<Vec<usize>> {
+    match *err.kind {
+        ErrorKind::InsertMany(InsertManyError {
+            write_errors: Some(ref errors),
+            write_concern_error: None,
+            ..
+        }) if errors.iter().all(|e| e.code == DUPLICATE_KEY) => {
+            Some(errors.iter().map(|e| e.index).collect())
+        }
+        _ => None,
+    }
+}
+
 #[derive(Clone)]
 pub struct DB {
     client: Client,
     name: String,
+    /// shared between clones, since they use the same database
+    cache: Arc<DbCache>,
 }
 
 impl DB {
@@ -153,12 +287,14 @@ impl DB {
         let options = ClientOptions::parse(db_url.into()).await?;
         let client = Client::with_options(options)?;
 
-        Ok(DB { client, name })
+        Ok(DB {
+            client,
+            name,
+            cache: Default::default(),
+        })
     }
 
     pub async fn migrate(&mut self) -> DbResult<()> {
-        /// some migrations doesn't realy need type of collection
-        type AnyCollection = Event;
         let events = self.get_database().await.collection::<Event>("events");
         events
             .create_index(
@@ -169,27 +305,48 @@ impl DB {
             )
             .await?;
 
-        // clear callbacks after a day because otherwise database will contain so much data
-        // for just button clicks
+        // ttl, that is set by bot's config, is kept, so index is only created if it's missing
         let callback_info = self
             .get_database()
             .await
-            .collection::<AnyCollection>("callback_info");
-        callback_info
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! {"created_at": 1})
-                    .options(
-                        IndexOptions::builder()
-                            .expire_after(Duration::from_secs(60 * 60 * 24 /* 1 day */))
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
+            .collection::<bson::Document>("callback_info");
+        match callback_info
+            .create_index(callback_ttl_index(DEFAULT_CALLBACK_TTL))
+            .await
+        {
+            Ok(_) => {}
+            Err(err) if is_index_conflict(&err) => {}
+            Err(err) => return Err(err.into()),
+        }
+
         Ok(())
     }
 
+    /// sets time after which callbacks of buttons are deleted,
+    /// since otherwise database will contain so much data for just button clicks
+    pub async fn set_callback_ttl(&mut self, ttl: Duration) -> DbResult<()> {
+        let database = self.get_database().await;
+        let callback_info = database.collection::<bson::Document>("callback_info");
+
+        match callback_info.create_index(callback_ttl_index(ttl)).await {
+            Ok(_) => Ok(()),
+            // index already exists with other ttl
+            Err(err) if is_index_conflict(&err) => {
+                database
+                    .run_command(doc! {
+                        "collMod": "callback_info",
+                        "index": {
+                            "keyPattern": {"created_at": 1},
+                            "expireAfterSeconds": ttl.as_secs() as i64,
+                        },
+                    })
+                    .await?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub async fn init<S: Into<String>>(db_url: S, name: String) -> DbResult<Self> {
         let mut db = Self::new(db_url, name).await?;
         db.migrate().await?;
@@ -198,7 +355,11 @@ impl DB {
     }
 
     pub fn with_name(self, name: String) -> Self {
-        Self { name, ..self }
+        Self {
+            name,
+            cache: Default::default(),
+            ..self
+        }
     }
 }
 
@@ -220,6 +381,10 @@ impl CallDB for DB {
     async fn get_database_immut(&self) -> Database {
         self.client.database(&self.name)
     }
+
+    fn cache(&self) -> Option<&DbCache> {
+        Some(&self.cache)
+    }
 }
 
 #[async_trait]
@@ -237,6 +402,8 @@ pub enum DbError {
     MongodbError(#[from] mongodb::error::Error),
     #[error("error while coverting values: {0}")]
     SerdeJsonError(#[from] serde_json::error::Error),
+    #[error("error while serializing value to bson: {0}")]
+    BsonSerError(#[from] bson::ser::Error),
 }
 pub type DbResult<T> = Result<T, DbError>;
 
@@ -245,6 +412,10 @@ pub trait CallDB {
     //type C;
     async fn get_database(&mut self) -> Database;
     async fn get_database_immut(&self) -> Database;
+    /// cache of literals and media, see [`cache`]
+    fn cache(&self) -> Option<&DbCache> {
+        None
+    }
     //async fn get_pool(&mut self) -> PooledConnection<'_, AsyncDieselConnectionManager<C>>;
     async fn get_users(&self) -> DbResult<Vec<User>> {
         let db = self.get_database_immut().await;
@@ -253,6 +424,18 @@ pub trait CallDB {
         Ok(users.find(doc! {}).await?.try_collect().await?)
     }
 
+    /// users, that have not blocked bot
+    async fn get_active_users(&self) -> DbResult<Vec<User>> {
+        let db = self.get_database_immut().await;
+        let users = db.collection::<User>("users");
+
+        Ok(users
+            .find(doc! {"blocked_at": null})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
     async fn get_users_by_ids(&self, ids: Vec<i64>) -> DbResult<Vec<User>> {
         let db = self.get_database_immut().await;
         let users = db.collection::<User>("users");
@@ -264,12 +447,16 @@ pub trait CallDB {
             .await?)
     }
 
+    /// random users, that have not blocked bot
     async fn get_random_users(&self, n: u32) -> DbResult<Vec<User>> {
         let db = self.get_database_immut().await;
         let users = db.collection::<User>("users");
 
         let random_users: Vec<bson::Document> = users
-            .aggregate(vec![doc! {"$sample": {"size": n}}])
+            .aggregate(vec![
+                doc! {"$match": {"blocked_at": null}},
+                doc! {"$sample": {"size": n}},
+            ])
             .await?
             .try_collect()
             .await?;
@@ -301,6 +488,21 @@ pub trait CallDB {
         Ok(())
     }
 
+    /// user, that can't get messages, e.g. has blocked bot, is not notified anymore
+    async fn set_user_blocked(&mut self, userid: i64, blocked: bool) -> DbResult<()> {
+        let db = self.get_database().await;
+        let users = db.collection::<User>("users");
+
+        let update = match blocked {
+            true => doc! { "$set": { "blocked_at": bson::DateTime::now() } },
+            false => doc! { "$unset": { "blocked_at": "" } },
+        };
+        users.update_one(doc! { "id": userid }, update).await?;
+
+        Ok(())
+    }
+
+    /// called on user's updates, so user is not blocked anymore
     async fn get_or_init_user(&mut self, userid: i64, firstname: &str) -> DbResult<User> {
         let db = self.get_database().await;
         let users = db.collection::<User>("users");
@@ -310,7 +512,12 @@ pub trait CallDB {
                 doc! { "id": userid },
                 doc! {
                     "$set": doc! { "first_name": firstname},
-                    "$setOnInsert": doc! { "is_admin": false, "metas": [] },
+                    "$unset": doc! { "blocked_at": "" },
+                    "$setOnInsert": doc! {
+                        "is_admin": false,
+                        "metas": [],
+                        "created_at": bson::DateTime::now(),
+                    },
                 },
             )
             .upsert(true)
@@ -400,13 +607,70 @@ pub trait CallDB {
         Ok(())
     }
 
+    /// marks messages as sent together, so they are replaced and deleted together
+    async fn set_message_group(&mut self, chatid: i64, messageids: &[i32]) -> DbResult<()> {
+        let db = self.get_database().await;
+        let messages = db.collection::<Message>("messages");
+        let ids: Vec<i64> = messageids.iter().map(|id| *id as i64).collect();
+
+        messages
+            .update_many(
+                doc! {
+                    "chat_id": chatid,
+                    "message_id": { "$in": &ids }
+                },
+                doc! { "$set": { "group_message_ids": &ids } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn drop_messages(&mut self, chatid: i64, messageids: &[i32]) -> DbResult<usize> {
+        let db = self.get_database().await;
+        let messages = db.collection::<Message>("messages");
+        let ids: Vec<i64> = messageids.iter().map(|id| *id as i64).collect();
+
+        let deleted_count = messages
+            .delete_many(doc! {
+                "chat_id": chatid,
+                "message_id": { "$in": ids }
+            })
+            .await?
+            .deleted_count;
+
+        Ok(deleted_count as usize)
+    }
+
     async fn get_literal(&self, literal: &str) -> DbResult<Option<Literal>> {
+        self.get_literal_lang(literal, &Langs::default()).await
+    }
+
+    /// literal in the most suitable language, see [`Langs`]
+    async fn get_literal_lang(&self, literal: &str, langs: &Langs) -> DbResult<Option<Literal>> {
         let db = self.get_database_immut().await;
+        let generation = match self.cache() {
+            Some(cache) => {
+                cache.validate(&db).await?;
+                if let Some(literals) = cache.get_literals(literal) {
+                    return Ok(langs.choose(literals, |l| l.lang.as_deref()));
+                }
+                cache.generation()
+            }
+            None => 0,
+        };
         let messages = db.collection::<Literal>("literals");
 
-        let literal = messages.find_one(doc! { "token": literal }).await?;
+        let literals: Vec<Literal> = messages
+            .find(doc! { "token": literal })
+            .await?
+            .try_collect()
+            .await?;
+        if let Some(cache) = self.cache() {
+            cache.set_literals(literal, literals.clone(), generation);
+        }
 
-        Ok(literal)
+        Ok(langs.choose(literals, |l| l.lang.as_deref()))
     }
 
     async fn get_literal_value(&self, literal: &str) -> DbResult<Option<String>> {
@@ -415,17 +679,40 @@ pub trait CallDB {
         Ok(literal.map(|l| l.value))
     }
 
+    async fn get_literal_value_lang(
+        &self,
+        literal: &str,
+        langs: &Langs,
+    ) -> DbResult<Option<String>> {
+        let literal = self.get_literal_lang(literal, langs).await?;
+
+        Ok(literal.map(|l| l.value))
+    }
+
+    /// sets value shown in any language, if there is no value in user's one
     async fn set_literal(&mut self, literal: &str, valuestr: &str) -> DbResult<()> {
+        self.set_literal_lang(literal, None, valuestr).await
+    }
+
+    async fn set_literal_lang(
+        &mut self,
+        literal: &str,
+        lang: Option<&str>,
+        valuestr: &str,
+    ) -> DbResult<()> {
         let db = self.get_database().await;
         let literals = db.collection::<Literal>("literals");
 
         literals
             .update_one(
-                doc! { "token": literal },
+                doc! { "token": literal, "lang": lang },
                 doc! { "$set": { "value": valuestr } },
             )
             .upsert(true)
             .await?;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(&db).await?;
+        }
 
         Ok(())
     }
@@ -434,15 +721,40 @@ pub trait CallDB {
         &mut self,
         literal: &str,
         variant: &str,
+    ) -> DbResult<Option<LiteralAlternative>> {
+        self.get_literal_alternative_lang(literal, variant, &Langs::default())
+            .await
+    }
+
+    async fn get_literal_alternative_lang(
+        &mut self,
+        literal: &str,
+        variant: &str,
+        langs: &Langs,
     ) -> DbResult<Option<LiteralAlternative>> {
         let db = self.get_database().await;
+        let generation = match self.cache() {
+            Some(cache) => {
+                cache.validate(&db).await?;
+                if let Some(literals) = cache.get_alternatives(literal, variant) {
+                    return Ok(langs.choose(literals, |l| l.lang.as_deref()));
+                }
+                cache.generation()
+            }
+            None => 0,
+        };
         let messages = db.collection::<LiteralAlternative>("literal_alternatives");
 
-        let literal = messages
-            .find_one(doc! { "token": literal, "variant": variant })
+        let literals: Vec<LiteralAlternative> = messages
+            .find(doc! { "token": literal, "variant": variant })
+            .await?
+            .try_collect()
             .await?;
+        if let Some(cache) = self.cache() {
+            cache.set_alternatives(literal, variant, literals.clone(), generation);
+        }
 
-        Ok(literal)
+        Ok(langs.choose(literals, |l| l.lang.as_deref()))
     }
 
     async fn get_literal_alternative_value(
@@ -455,22 +767,49 @@ pub trait CallDB {
         Ok(literal.map(|l| l.value))
     }
 
+    async fn get_literal_alternative_value_lang(
+        &mut self,
+        literal: &str,
+        variant: &str,
+        langs: &Langs,
+    ) -> DbResult<Option<String>> {
+        let literal = self
+            .get_literal_alternative_lang(literal, variant, langs)
+            .await?;
+
+        Ok(literal.map(|l| l.value))
+    }
+
     async fn set_literal_alternative(
         &mut self,
         literal: &str,
         variant: &str,
         valuestr: &str,
+    ) -> DbResult<()> {
+        self.set_literal_alternative_lang(literal, variant, None, valuestr)
+            .await
+    }
+
+    async fn set_literal_alternative_lang(
+        &mut self,
+        literal: &str,
+        variant: &str,
+        lang: Option<&str>,
+        valuestr: &str,
     ) -> DbResult<()> {
         let db = self.get_database().await;
         let literals = db.collection::<LiteralAlternative>("literal_alternatives");
 
         literals
             .update_one(
-                doc! { "token": literal, "variant": variant },
+                doc! { "token": literal, "variant": variant, "lang": lang },
                 doc! { "$set": { "value": valuestr } },
             )
             .upsert(true)
             .await?;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(&db).await?;
+        }
 
         Ok(())
     }
@@ -516,13 +855,26 @@ pub trait CallDB {
 
     async fn get_media(&mut self, literal: &str) -> DbResult<Vec<Media>> {
         let db = self.get_database().await;
+        let generation = match self.cache() {
+            Some(cache) => {
+                cache.validate(&db).await?;
+                if let Some(media_items) = cache.get_media(literal) {
+                    return Ok(media_items);
+                }
+                cache.generation()
+            }
+            None => 0,
+        };
         let media = db.collection::<Media>("media");
 
-        let media_items = media
+        let media_items: Vec<Media> = media
             .find(doc! { "token": literal })
             .await?
             .try_collect()
             .await?;
+        if let Some(cache) = self.cache() {
+            cache.set_media(literal, media_items.clone(), generation);
+        }
 
         Ok(media_items)
     }
@@ -547,6 +899,9 @@ pub trait CallDB {
             .delete_many(doc! { "token": literal })
             .await?
             .deleted_count;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(&db).await?;
+        }
 
         Ok(deleted_count as usize)
     }
@@ -562,6 +917,9 @@ pub trait CallDB {
             })
             .await?
             .deleted_count;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(&db).await?;
+        }
 
         Ok(deleted_count as usize)
     }
@@ -569,7 +927,7 @@ pub trait CallDB {
     async fn add_media(
         &mut self,
         literal: &str,
-        mediatype: &str,
+        mediatype: MediaType,
         fileid: &str,
         media_group: Option<&str>,
     ) -> DbResult<Media> {
@@ -579,12 +937,15 @@ pub trait CallDB {
         let new_media = Media {
             _id: bson::oid::ObjectId::new(),
             token: literal.to_string(),
-            media_type: mediatype.to_string(),
+            media_type: mediatype,
             file_id: fileid.to_string(),
             media_group_id: media_group.map(|g| g.to_string()),
         };
 
         media.insert_one(&new_media).await?;
+        if let Some(cache) = self.cache() {
+            cache.invalidate(&db).await?;
+        }
 
         Ok(new_media)
     }
@@ -0,0 +1,311 @@
+//! Log of notifications' runs and their deliveries to users, so notificator can continue
+//! after restart without sending the same notification to user twice
+use bson::doc;
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::options::IndexOptions;
+use mongodb::IndexModel;
+use serde::{Deserialize, Serialize};
+
+use super::{duplicate_indexes, DbResult};
+use crate::CallDB;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// waiting for its time
+    Scheduled,
+    /// deliveries to users are created
+    Started,
+    /// was not started in time, e.g. bot was stopped
+    Missed,
+}
+
+/// Sending of notification to all its users at `scheduled_at`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationRun {
+    /// notification's name and time, so the same run is not scheduled twice
+    pub _id: String,
+    pub notification: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub scheduled_at: DateTime<Utc>,
+    pub status: RunStatus,
+}
+
+impl NotificationRun {
+    pub fn new(notification: String, scheduled_at: DateTime<Utc>) -> Self {
+        Self {
+            _id: format!("{notification}@{}", scheduled_at.timestamp()),
+            notification,
+            scheduled_at,
+            status: RunStatus::Scheduled,
+        }
+    }
+
+    /// run of notification, that is sent once after bot's start, its id doesn't depend
+    /// on time, since bot's start changes on restart
+    pub fn once(notification: String, scheduled_at: DateTime<Utc>) -> Self {
+        Self {
+            _id: format!("{notification}@once"),
+            ..Self::new(notification, scheduled_at)
+        }
+    }
+
+    /// stores run, if it is not stored yet
+    pub async fn schedule<D: CallDB>(&self, db: &mut D) -> DbResult<()> {
+        let db = db.get_database().await;
+        let runs = db.collection::<Self>("notification_runs");
+
+        runs.update_one(
+            doc! { "_id": &self._id },
+            doc! {
+                "$setOnInsert": {
+                    "notification": &self.notification,
+                    "scheduled_at": bson::DateTime::from_chrono(self.scheduled_at),
+                    "status": bson::to_bson(&self.status)?,
+                }
+            },
+        )
+        .upsert(true)
+        .await?;
+
+        Ok(())
+    }
+
+    /// scheduled runs, which time has come
+    pub async fn get_due<D: CallDB>(db: &mut D, now: DateTime<Utc>) -> DbResult<Vec<Self>> {
+        let db = db.get_database().await;
+        let runs = db.collection::<Self>("notification_runs");
+
+        let due = runs
+            .find(doc! {
+                "scheduled_at": { "$lte": bson::DateTime::from_chrono(now) },
+                "status": bson::to_bson(&RunStatus::Scheduled)?,
+            })
+            .sort(doc! {"scheduled_at": 1})
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(due)
+    }
+
+    pub async fn set_status<D: CallDB>(&self, db: &mut D, status: RunStatus) -> DbResult<()> {
+        let db = db.get_database().await;
+        let runs = db.collection::<Self>("notification_runs");
+
+        runs.update_one(
+            doc! { "_id": &self._id },
+            doc! { "$set": { "status": bson::to_bson(&status)? } },
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// waiting for `next_attempt_at`
+    Pending,
+    /// is being sent right now, if notificator was stopped, message may be not delivered
+    Sending,
+    Sent,
+    /// notification has no message for user
+    Skipped,
+    Failed,
+}
+
+/// Sending of notification's message to one user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationDelivery {
+    pub _id: bson::oid::ObjectId,
+    /// id of [`NotificationRun`] or of scheduled message
+    pub run: String,
+    pub notification: String,
+    pub user_id: i64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub next_attempt_at: DateTime<Utc>,
+    /// error of the last attempt
+    pub error: Option<String>,
+}
+
+impl NotificationDelivery {
+    pub fn new(run: String, notification: String, user_id: i64) -> Self {
+        Self {
+            _id: Default::default(),
+            run,
+            notification,
+            user_id,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: Utc::now(),
+            error: None,
+        }
+    }
+
+    pub async fn create_indexes<D: CallDB>(db: &mut D) -> DbResult<()> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        deliveries
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"run": 1, "user_id": 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+            )
+            .await?;
+        deliveries
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"status": 1, "next_attempt_at": 1})
+                    .build(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// stores deliveries with a single request, deliveries of run to users,
+    /// that are already stored, are kept as is
+    pub async fn store_many<D: CallDB>(db: &mut D, deliveries: Vec<Self>) -> DbResult<()> {
+        if deliveries.is_empty() {
+            return Ok(());
+        }
+        let db = db.get_database().await;
+        let collection = db.collection::<Self>("notification_deliveries");
+
+        match collection.insert_many(&deliveries).ordered(false).await {
+            Ok(_) => Ok(()),
+            Err(err) if duplicate_indexes(&err).is_some() => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// pending deliveries, which time of the next attempt has come
+    pub async fn get_due<D: CallDB>(db: &mut D, now: DateTime<Utc>) -> DbResult<Vec<Self>> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        let due = deliveries
+            .find(doc! {
+                "status": bson::to_bson(&DeliveryStatus::Pending)?,
+                "next_attempt_at": { "$lte": bson::DateTime::from_chrono(now) },
+            })
+            .sort(doc! {"next_attempt_at": 1})
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(due)
+    }
+
+    /// if there are deliveries, that are waiting for the next attempt
+    pub async fn has_pending<D: CallDB>(db: &mut D) -> DbResult<bool> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        let pending = deliveries
+            .find_one(doc! { "status": bson::to_bson(&DeliveryStatus::Pending)? })
+            .await?;
+
+        Ok(pending.is_some())
+    }
+
+    /// marks delivery as being sent, returns false if it is not pending anymore
+    pub async fn claim<D: CallDB>(&mut self, db: &mut D) -> DbResult<bool> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        let result = deliveries
+            .update_one(
+                doc! {
+                    "_id": self._id,
+                    "status": bson::to_bson(&DeliveryStatus::Pending)?,
+                },
+                doc! {
+                    "$set": { "status": bson::to_bson(&DeliveryStatus::Sending)? },
+                    "$inc": { "attempts": 1 },
+                },
+            )
+            .await?;
+        if result.modified_count != 1 {
+            return Ok(false);
+        }
+        self.status = DeliveryStatus::Sending;
+        self.attempts += 1;
+
+        Ok(true)
+    }
+
+    pub async fn set_status<D: CallDB>(
+        &self,
+        db: &mut D,
+        status: DeliveryStatus,
+        error: Option<String>,
+    ) -> DbResult<()> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        deliveries
+            .update_one(
+                doc! { "_id": self._id },
+                doc! { "$set": { "status": bson::to_bson(&status)?, "error": error } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// returns delivery to pending, so it is sent again `at`
+    pub async fn retry_at<D: CallDB>(
+        &self,
+        db: &mut D,
+        at: DateTime<Utc>,
+        error: String,
+    ) -> DbResult<()> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        deliveries
+            .update_one(
+                doc! { "_id": self._id },
+                doc! {
+                    "$set": {
+                        "status": bson::to_bson(&DeliveryStatus::Pending)?,
+                        "next_attempt_at": bson::DateTime::from_chrono(at),
+                        "error": error,
+                    }
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// marks deliveries, that were being sent when notificator stopped, as failed,
+    /// since it is unknown if they were delivered, and sending them again can duplicate them
+    pub async fn fail_interrupted<D: CallDB>(db: &mut D) -> DbResult<u64> {
+        let db = db.get_database().await;
+        let deliveries = db.collection::<Self>("notification_deliveries");
+
+        let result = deliveries
+            .update_many(
+                doc! { "status": bson::to_bson(&DeliveryStatus::Sending)? },
+                doc! {
+                    "$set": {
+                        "status": bson::to_bson(&DeliveryStatus::Failed)?,
+                        "error": "interrupted while sending",
+                    }
+                },
+            )
+            .await?;
+
+        Ok(result.modified_count)
+    }
+}
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
-use mongodb::Database;
+use futures::TryStreamExt;
+use mongodb::{bson::Document, Database};
 
 use super::CallDB;
 use serde_json::Value;
@@ -22,15 +23,54 @@ pub trait RawCall {
         let db = self.get_database().await;
         let value = db.collection::<Value>(collection);
 
-        let map = match query {
-            Value::Object(map) => map,
-            _ => return Err(RawCallError::NotAMapError("query is not a map".to_string())),
-        };
-
-        let doc = map.try_into()?;
+        let doc = to_document(query, "query")?;
         let ret = value.find_one(doc).await?;
         Ok(ret)
     }
+
+    async fn find(&mut self, collection: &str, query: Value) -> RawCallResult<Vec<Value>> {
+        let db = self.get_database().await;
+        let value = db.collection::<Value>(collection);
+
+        let doc = to_document(query, "query")?;
+        let ret = value.find(doc).await?.try_collect().await?;
+        Ok(ret)
+    }
+
+    /// returns id of inserted document
+    async fn insert_one(&mut self, collection: &str, document: Value) -> RawCallResult<Value> {
+        let db = self.get_database().await;
+        let value = db.collection::<Document>(collection);
+
+        let doc = to_document(document, "document")?;
+        let ret = value.insert_one(doc).await?;
+        Ok(ret.inserted_id.into_relaxed_extjson())
+    }
+
+    /// returns count of modified documents
+    async fn update_many(
+        &mut self,
+        collection: &str,
+        query: Value,
+        update: Value,
+    ) -> RawCallResult<u64> {
+        let db = self.get_database().await;
+        let value = db.collection::<Document>(collection);
+
+        let query = to_document(query, "query")?;
+        let update = to_document(update, "update")?;
+        let ret = value.update_many(query, update).await?;
+        Ok(ret.modified_count)
+    }
+}
+
+fn to_document(value: Value, name: &str) -> RawCallResult<Document> {
+    let map = match value {
+        Value::Object(map) => map,
+        _ => return Err(RawCallError::NotAMapError(format!("{name} is not a map"))),
+    };
+
+    Ok(map.try_into()?)
 }
 
 #[async_trait]
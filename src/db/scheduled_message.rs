@@ -0,0 +1,106 @@
+use bson::doc;
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use mongodb::options::IndexOptions;
+use mongodb::IndexModel;
+use serde::{Deserialize, Serialize};
+
+use super::DbResult;
+use crate::CallDB;
+
+/// Message of notification, that should be sent to user at `send_at`,
+/// since timer of notification is started by user's event
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledMessage {
+    pub _id: bson::oid::ObjectId,
+    pub user_id: i64,
+    /// name of notification in bot's config
+    pub notification: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub send_at: DateTime<Utc>,
+    #[serde(default)]
+    pub sent: bool,
+}
+
+impl ScheduledMessage {
+    pub fn new(user_id: i64, notification: String, send_at: DateTime<Utc>) -> Self {
+        Self {
+            _id: Default::default(),
+            user_id,
+            notification,
+            send_at,
+            sent: false,
+        }
+    }
+
+    pub async fn create_indexes<D: CallDB>(db: &mut D) -> DbResult<()> {
+        let db = db.get_database().await;
+        let sm = db.collection::<Self>("scheduled_messages");
+
+        sm.create_index(
+            IndexModel::builder()
+                .keys(doc! {"user_id": 1, "notification": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+        sm.create_index(IndexModel::builder().keys(doc! {"send_at": 1}).build())
+            .await?;
+
+        Ok(())
+    }
+
+    /// schedules message, each notification is scheduled only once for user,
+    /// so repeated events do not restart timer
+    pub async fn store<D: CallDB>(&self, db: &mut D) -> DbResult<()> {
+        let db = db.get_database().await;
+        let sm = db.collection::<Self>("scheduled_messages");
+
+        sm.update_one(
+            doc! {
+                "user_id": self.user_id,
+                "notification": &self.notification,
+            },
+            doc! {
+                "$setOnInsert": {
+                    "send_at": bson::DateTime::from_chrono(self.send_at),
+                    "sent": false,
+                }
+            },
+        )
+        .upsert(true)
+        .await?;
+
+        Ok(())
+    }
+
+    /// messages, which time to be sent has come
+    pub async fn get_due<D: CallDB>(db: &mut D, now: DateTime<Utc>) -> DbResult<Vec<Self>> {
+        let db = db.get_database().await;
+        let sm = db.collection::<Self>("scheduled_messages");
+
+        let due = sm
+            .find(doc! {
+                "send_at": { "$lte": bson::DateTime::from_chrono(now) },
+                "sent": false,
+            })
+            .sort(doc! {"send_at": 1})
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(due)
+    }
+
+    /// message is passed to notificator's deliveries, see [`super::notification_delivery`]
+    pub async fn mark_sent<D: CallDB>(&self, db: &mut D) -> DbResult<()> {
+        let db = db.get_database().await;
+        let sm = db.collection::<Self>("scheduled_messages");
+
+        sm.update_one(doc! {"_id": self._id}, doc! {"$set": {"sent": true}})
+            .await?;
+
+        Ok(())
+    }
+}
@@ -26,3 +26,27 @@ async fn test_store() {
 
     assert!(ci.is_some());
 }
+
+#[tokio::test]
+async fn test_store_many_content_id() {
+    let mut db = setup_db().await;
+
+    let new_ci = || {
+        CI::new_with_literal(Callback::NextPage, "test_store_many".to_string())
+            .with_content_id()
+            .unwrap()
+    };
+    let (ci1, ci2) = (new_ci(), new_ci());
+    assert_eq!(ci1.get_id(), ci2.get_id());
+    assert_ne!(ci1.get_id(), CI::new(Callback::NextPage).get_id());
+
+    let cis = vec![ci1, CI::new(Callback::MoreInfo)];
+    let cis = CI::store_many(&mut db, cis).await.unwrap();
+    // storing the same callback again should not fail
+    CI::store_many(&mut db, vec![ci2]).await.unwrap();
+
+    for ci in cis {
+        let stored = CI::get(&mut db, &ci.get_id()).await.unwrap();
+        assert!(stored.is_some());
+    }
+}
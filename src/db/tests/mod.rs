@@ -1,9 +1,21 @@
 #![allow(clippy::unwrap_used)]
 
 mod callback_info_tests;
+use std::time::Duration;
+
+use bson::doc;
+use chrono::{TimeDelta, Utc};
 use dotenvy;
+use futures::TryStreamExt;
 
+use super::cache::CHECK_INTERVAL;
+use super::notification_delivery::{
+    DeliveryStatus, NotificationDelivery, NotificationRun, RunStatus,
+};
+use super::scheduled_message::ScheduledMessage;
 use super::CallDB;
+use super::Langs;
+use super::MediaType;
 use super::DB;
 
 async fn setup_db() -> DB {
@@ -23,7 +35,12 @@ async fn test_get_media() {
     assert_eq!(media_items.len(), 0);
 
     let _result = db
-        .add_media("test_get_media_literal", "photo", "file_id_1", None)
+        .add_media(
+            "test_get_media_literal",
+            MediaType::Photo,
+            "file_id_1",
+            None,
+        )
         .await
         .unwrap();
 
@@ -31,7 +48,12 @@ async fn test_get_media() {
     assert_eq!(media_items.len(), 1);
 
     let _result = db
-        .add_media("test_get_media_literal", "video", "file_id_2", None)
+        .add_media(
+            "test_get_media_literal",
+            MediaType::Video,
+            "file_id_2",
+            None,
+        )
         .await
         .unwrap();
 
@@ -47,7 +69,7 @@ async fn test_add_media() {
     let mut db = setup_db().await;
 
     let literal = "test_literal";
-    let media_type = "photo";
+    let media_type = MediaType::Photo;
     let file_id = "LjaldhAOh";
 
     let _result = db.drop_media(literal).await.unwrap();
@@ -75,7 +97,12 @@ async fn test_drop_media() {
     let _result = db.drop_media("test_drop_media_literal").await.unwrap();
 
     let _result = db
-        .add_media("test_drop_media_literal", "photo", "file_id_1", None)
+        .add_media(
+            "test_drop_media_literal",
+            MediaType::Photo,
+            "file_id_1",
+            None,
+        )
         .await
         .unwrap();
 
@@ -106,7 +133,7 @@ async fn test_is_media_group_exists() {
     assert!(!exists);
 
     let _ = db
-        .add_media(literal, "photo", "file_id_1", Some(media_group))
+        .add_media(literal, MediaType::Photo, "file_id_1", Some(media_group))
         .await
         .unwrap();
 
@@ -128,11 +155,11 @@ async fn test_drop_media_except() {
     let _ = db.drop_media(literal).await.unwrap();
 
     let _ = db
-        .add_media(literal, "photo", "file_id_2", None)
+        .add_media(literal, MediaType::Photo, "file_id_2", None)
         .await
         .unwrap();
     let _ = db
-        .add_media(literal, "photo", "file_id_3", None)
+        .add_media(literal, MediaType::Photo, "file_id_3", None)
         .await
         .unwrap();
 
@@ -144,15 +171,15 @@ async fn test_drop_media_except() {
     assert_eq!(media_items.len(), 0);
 
     let _ = db
-        .add_media(literal, "photo", "file_id_1", Some(media_group))
+        .add_media(literal, MediaType::Photo, "file_id_1", Some(media_group))
         .await
         .unwrap();
     let _ = db
-        .add_media(literal, "photo", "file_id_2", None)
+        .add_media(literal, MediaType::Photo, "file_id_2", None)
         .await
         .unwrap();
     let _ = db
-        .add_media(literal, "photo", "file_id_3", None)
+        .add_media(literal, MediaType::Photo, "file_id_3", None)
         .await
         .unwrap();
 
@@ -162,11 +189,11 @@ async fn test_drop_media_except() {
     let _ = db.drop_media(literal).await.unwrap();
 
     let _ = db
-        .add_media(literal, "photo", "file_id_1", Some(media_group))
+        .add_media(literal, MediaType::Photo, "file_id_1", Some(media_group))
         .await
         .unwrap();
     let _ = db
-        .add_media(literal, "photo", "file_id_2", Some(media_group))
+        .add_media(literal, MediaType::Photo, "file_id_2", Some(media_group))
         .await
         .unwrap();
 
@@ -177,6 +204,29 @@ async fn test_drop_media_except() {
     let _ = db.drop_media(literal).await.unwrap();
 }
 
+#[tokio::test]
+async fn test_message_group() {
+    let mut db = setup_db().await;
+
+    let chat_id = -1;
+    let ids = [101, 102, 103];
+    let _ = db.drop_messages(chat_id, &ids).await.unwrap();
+
+    for id in ids {
+        db.set_message_literal(chat_id, id, "test_message_group")
+            .await
+            .unwrap();
+    }
+    db.set_message_group(chat_id, &ids).await.unwrap();
+
+    let msg = db.get_message(chat_id, 102).await.unwrap().unwrap();
+    assert_eq!(msg.group_message_ids, vec![101, 102, 103]);
+
+    let deleted = db.drop_messages(chat_id, &ids).await.unwrap();
+    assert_eq!(deleted, 3);
+    assert!(db.get_message(chat_id, 101).await.unwrap().is_none());
+}
+
 #[tokio::test]
 async fn test_get_random_users() {
     let mut db = setup_db().await;
@@ -186,3 +236,318 @@ async fn test_get_random_users() {
     let users = db.get_random_users(1).await.unwrap();
     assert_eq!(users.len(), 1);
 }
+
+async fn literal_lang(db: &DB, literal: &str, langs: Langs) -> String {
+    db.get_literal_value_lang(literal, &langs)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_literal_langs() {
+    let mut db = setup_db().await;
+
+    let literal = "test_literal_langs";
+    db.set_literal(literal, "any").await.unwrap();
+    db.set_literal_lang(literal, Some("ru"), "ru")
+        .await
+        .unwrap();
+    db.set_literal_lang(literal, Some("de"), "de")
+        .await
+        .unwrap();
+
+    let langs = Langs::new(Some("ru"), Some("de"));
+    assert_eq!(literal_lang(&db, literal, langs).await, "ru");
+    let langs = Langs::new(Some("en"), Some("de"));
+    assert_eq!(literal_lang(&db, literal, langs).await, "de");
+    let langs = Langs::new(Some("en"), None);
+    assert_eq!(literal_lang(&db, literal, langs).await, "any");
+    assert_eq!(db.get_literal_value(literal).await.unwrap().unwrap(), "any");
+
+    // value without language is updated, the others are kept
+    db.set_literal(literal, "any2").await.unwrap();
+    let langs = Langs::new(Some("ru"), None);
+    assert_eq!(literal_lang(&db, literal, langs).await, "ru");
+    assert_eq!(literal_lang(&db, literal, Langs::default()).await, "any2");
+}
+
+#[tokio::test]
+async fn test_literal_cache_invalidation() {
+    let mut db = setup_db().await;
+    // other process, with its own cache
+    let mut other = setup_db().await;
+
+    let literal = "test_literal_cache_invalidation";
+    db.set_literal(literal, "first").await.unwrap();
+    assert_eq!(
+        other.get_literal_value(literal).await.unwrap().unwrap(),
+        "first"
+    );
+
+    db.set_literal(literal, "second").await.unwrap();
+    assert_eq!(
+        db.get_literal_value(literal).await.unwrap().unwrap(),
+        "second"
+    );
+
+    tokio::time::sleep(CHECK_INTERVAL).await;
+    assert_eq!(
+        other.get_literal_value(literal).await.unwrap().unwrap(),
+        "second"
+    );
+
+    other.drop_media(literal).await.unwrap();
+    other
+        .add_media(literal, MediaType::Photo, "file_id_1", None)
+        .await
+        .unwrap();
+    assert_eq!(other.get_media(literal).await.unwrap().len(), 1);
+    other.drop_media(literal).await.unwrap();
+    assert_eq!(other.get_media(literal).await.unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn test_scheduled_messages() {
+    let mut db = setup_db().await;
+    ScheduledMessage::create_indexes(&mut db).await.unwrap();
+
+    let notification = "test_scheduled_messages";
+    db.get_database()
+        .await
+        .collection::<ScheduledMessage>("scheduled_messages")
+        .delete_many(doc! {"notification": notification})
+        .await
+        .unwrap();
+
+    let now = Utc::now();
+    let due = |sms: Vec<ScheduledMessage>| {
+        sms.into_iter()
+            .filter(|sm| sm.notification == notification)
+            .collect::<Vec<_>>()
+    };
+
+    ScheduledMessage::new(1, notification.to_string(), now - TimeDelta::minutes(1))
+        .store(&mut db)
+        .await
+        .unwrap();
+    // timer is not restarted by repeated event
+    ScheduledMessage::new(1, notification.to_string(), now + TimeDelta::hours(1))
+        .store(&mut db)
+        .await
+        .unwrap();
+    ScheduledMessage::new(2, notification.to_string(), now + TimeDelta::hours(1))
+        .store(&mut db)
+        .await
+        .unwrap();
+
+    let sms = due(ScheduledMessage::get_due(&mut db, now).await.unwrap());
+    assert_eq!(sms.len(), 1);
+    assert_eq!(sms[0].user_id, 1);
+
+    sms[0].mark_sent(&mut db).await.unwrap();
+    let sms = due(ScheduledMessage::get_due(&mut db, now).await.unwrap());
+    assert_eq!(sms.len(), 0);
+}
+
+#[tokio::test]
+async fn test_notification_deliveries() {
+    let mut db = setup_db().await;
+    NotificationDelivery::create_indexes(&mut db).await.unwrap();
+
+    let at = Utc::now() - TimeDelta::minutes(1);
+    let run = NotificationRun::new("test_notification_deliveries".to_string(), at);
+    let database = db.get_database().await;
+    database
+        .collection::<NotificationRun>("notification_runs")
+        .delete_many(doc! {"_id": &run._id})
+        .await
+        .unwrap();
+    database
+        .collection::<NotificationDelivery>("notification_deliveries")
+        .delete_many(doc! {"run": &run._id})
+        .await
+        .unwrap();
+
+    // the same run is scheduled once
+    run.schedule(&mut db).await.unwrap();
+    run.schedule(&mut db).await.unwrap();
+    let runs = NotificationRun::get_due(&mut db, Utc::now()).await.unwrap();
+    assert_eq!(runs.iter().filter(|r| r._id == run._id).count(), 1);
+    run.set_status(&mut db, RunStatus::Started).await.unwrap();
+    let runs = NotificationRun::get_due(&mut db, Utc::now()).await.unwrap();
+    assert!(!runs.iter().any(|r| r._id == run._id));
+
+    let deliveries = |ids: &[i64]| {
+        ids.iter()
+            .map(|id| NotificationDelivery::new(run._id.clone(), run.notification.clone(), *id))
+            .collect::<Vec<_>>()
+    };
+    let due = async |db: &mut DB| {
+        NotificationDelivery::get_due(db, Utc::now())
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.run == run._id)
+            .collect::<Vec<_>>()
+    };
+    NotificationDelivery::store_many(&mut db, deliveries(&[1, 2]))
+        .await
+        .unwrap();
+    // restarted run does not duplicate deliveries
+    NotificationDelivery::store_many(&mut db, deliveries(&[1, 2, 3]))
+        .await
+        .unwrap();
+    let mut pending = due(&mut db).await;
+    assert_eq!(pending.len(), 3);
+
+    assert!(pending[0].claim(&mut db).await.unwrap());
+    let mut claimed = pending[0].clone();
+    claimed.status = DeliveryStatus::Pending;
+    assert!(!claimed.claim(&mut db).await.unwrap());
+    pending[0]
+        .set_status(&mut db, DeliveryStatus::Sent, None)
+        .await
+        .unwrap();
+
+    assert!(pending[1].claim(&mut db).await.unwrap());
+    pending[1]
+        .retry_at(
+            &mut db,
+            Utc::now() + TimeDelta::hours(1),
+            "timeout".to_string(),
+        )
+        .await
+        .unwrap();
+
+    assert!(pending[2].claim(&mut db).await.unwrap());
+    NotificationDelivery::fail_interrupted(&mut db)
+        .await
+        .unwrap();
+    assert_eq!(due(&mut db).await.len(), 0);
+}
+
+#[tokio::test]
+async fn test_blocked_users() {
+    let mut db = setup_db().await;
+    let user_id = 2001;
+    let is_active = async |db: &DB| {
+        db.get_active_users()
+            .await
+            .unwrap()
+            .iter()
+            .any(|user| user.id == user_id)
+    };
+
+    db.get_or_init_user(user_id, "Blocking").await.unwrap();
+    db.set_user_blocked(user_id, true).await.unwrap();
+    assert!(!is_active(&db).await);
+    let user = db.get_users_by_ids(vec![user_id]).await.unwrap().pop();
+    assert!(user.unwrap().blocked_at.is_some());
+
+    // user writes to bot again
+    let user = db.get_or_init_user(user_id, "Blocking").await.unwrap();
+    assert!(user.blocked_at.is_none());
+    assert!(is_active(&db).await);
+
+    db.set_user_blocked(user_id, true).await.unwrap();
+    db.set_user_blocked(user_id, false).await.unwrap();
+    assert!(is_active(&db).await);
+}
+
+#[tokio::test]
+async fn test_callback_ttl_is_kept() {
+    let mut db = setup_db().await;
+    let ttl = Duration::from_secs(60 * 60 * 2);
+
+    db.set_callback_ttl(ttl).await.unwrap();
+    // ttl of bot's config should not be reset by migration of the next DB::init
+    db.migrate().await.unwrap();
+
+    let indexes: Vec<_> = db
+        .get_database()
+        .await
+        .collection::<bson::Document>("callback_info")
+        .list_indexes()
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let index = indexes
+        .iter()
+        .find(|index| index.keys == doc! {"created_at": 1})
+        .unwrap();
+    assert_eq!(index.options.as_ref().unwrap().expire_after, Some(ttl));
+}
+
+#[tokio::test]
+async fn test_notification_run_once() {
+    let mut db = setup_db().await;
+
+    let at = Utc::now() - TimeDelta::minutes(1);
+    let run = NotificationRun::once("test_notification_run_once".to_string(), at);
+    db.get_database()
+        .await
+        .collection::<NotificationRun>("notification_runs")
+        .delete_many(doc! {"_id": &run._id})
+        .await
+        .unwrap();
+
+    run.schedule(&mut db).await.unwrap();
+    run.set_status(&mut db, RunStatus::Started).await.unwrap();
+
+    // after restart, time relative to bot's start is later, but run is not scheduled again
+    let restarted = NotificationRun::once(run.notification.clone(), at + TimeDelta::days(1));
+    restarted.schedule(&mut db).await.unwrap();
+    let runs = NotificationRun::get_due(&mut db, at + TimeDelta::days(2))
+        .await
+        .unwrap();
+    assert!(!runs.iter().any(|r| r._id == run._id));
+}
+
+#[tokio::test]
+async fn test_notification_run_date_changed() {
+    let mut db = setup_db().await;
+
+    let name = "test_notification_run_date_changed".to_string();
+    db.get_database()
+        .await
+        .collection::<NotificationRun>("notification_runs")
+        .delete_many(doc! {"notification": &name})
+        .await
+        .unwrap();
+
+    let at = Utc::now() - TimeDelta::days(1);
+    let run = NotificationRun::new(name.clone(), at);
+    run.schedule(&mut db).await.unwrap();
+    run.set_status(&mut db, RunStatus::Started).await.unwrap();
+
+    // date of `once` is changed in config, so it is sent again
+    let changed = NotificationRun::new(name, at + TimeDelta::hours(1));
+    assert_ne!(changed._id, run._id);
+    changed.schedule(&mut db).await.unwrap();
+    let runs = NotificationRun::get_due(&mut db, Utc::now()).await.unwrap();
+    assert!(runs.iter().any(|r| r._id == changed._id));
+    assert!(!runs.iter().any(|r| r._id == run._id));
+}
+
+#[tokio::test]
+async fn test_first_seen_scheduled() {
+    let mut db = setup_db().await;
+    let user_id = 2002;
+    db.get_database()
+        .await
+        .collection::<bson::Document>("users")
+        .delete_many(doc! {"id": user_id})
+        .await
+        .unwrap();
+
+    let user = db.get_or_init_user(user_id, "First").await.unwrap();
+    assert!(!user.first_seen_scheduled);
+    assert!(user.mark_first_seen_scheduled(&mut db).await.unwrap());
+    // the next updates of user don't start timers again
+    assert!(!user.mark_first_seen_scheduled(&mut db).await.unwrap());
+    let user = db.get_or_init_user(user_id, "First").await.unwrap();
+    assert!(user.first_seen_scheduled);
+}
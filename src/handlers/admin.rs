@@ -5,7 +5,8 @@ use teloxide::dispatching::dialogue::serializer::Json;
 use teloxide::net::Download;
 use teloxide::prelude::*;
 use teloxide::sugar::request::RequestReplyExt;
-use teloxide::types::{MediaKind, MessageId, MessageKind, ParseMode};
+use teloxide::types::{FileMeta, MediaKind, MessageEntity, MessageId, MessageKind, ParseMode};
+use teloxide::utils::html;
 use teloxide::utils::render::RenderMessageTextHelper;
 use teloxide::{dptree, types::Update};
 
@@ -13,9 +14,9 @@ use futures::StreamExt;
 
 use crate::admin::{admin_command_handler, AdminCommands};
 use crate::bot_handler::BotHandler;
-use crate::db::bots::BotInstance;
+use crate::db::bots::{BotInstance, ScriptLang};
 use crate::db::message_forward::MessageForward;
-use crate::db::{CallDB, DB};
+use crate::db::{CallDB, MediaType, DB};
 use crate::mongodb_storage::MongodbStorage;
 use crate::{notify_admin, BotDialogue, BotError, BotResult, CallbackStore, State};
 
@@ -94,11 +95,12 @@ pub fn admin_handler() -> BotHandler {
         )
 }
 async fn newscript_handler(bot: Bot, mut db: DB, msg: Message, name: String) -> BotResult<()> {
-    let script = match msg.kind {
+    let (script, lang) = match msg.kind {
         MessageKind::Common(message) => {
             match message.media_kind {
                 MediaKind::Document(media_document) => {
                     let doc = media_document.document;
+                    let lang = ScriptLang::from_filename(doc.file_name.as_deref().unwrap_or(""));
                     let file = bot.get_file(doc.file.id).await?;
                     let mut stream = bot.download_file_stream(&file.path);
                     let mut buf: Vec<u8> = Vec::new();
@@ -118,7 +120,7 @@ async fn newscript_handler(bot: Bot, mut db: DB, msg: Message, name: String) ->
                     }
 
                     match String::from_utf8(buf) {
-                        Ok(s) => s,
+                        Ok(s) => (s, lang),
                         Err(err) => {
                             warn!("Failed to parse buf to string, err: {err}");
                             bot.send_message(msg.chat.id, format!("Failed to Convert file to script: file is not UTF-8, err: {err}")).await?;
@@ -143,7 +145,7 @@ async fn newscript_handler(bot: Bot, mut db: DB, msg: Message, name: String) ->
             return Ok(());
         }
     };
-    BotInstance::update_script(&mut db, &name, &script).await?;
+    BotInstance::update_script(&mut db, &name, &script, lang).await?;
 
     bot.send_message(msg.chat.id, "New script is set!").await?;
     Ok(())
@@ -192,12 +194,11 @@ async fn button_edit_callback(
         }
     };
 
-    let lang = "ru".to_string();
     dialogue
         .update(State::Edit {
             literal,
             variant: None,
-            lang,
+            lang: None,
             is_caption_set: false,
         })
         .await?;
@@ -252,13 +253,12 @@ async fn edit_msg_cmd_handler(
                     return Ok(());
                 }
             };
-            // TODO: language selector will be implemented in future 😈
-            let lang = "ru".to_string();
+            // value for specific language is set with /setliterallang
             dialogue
                 .update(State::Edit {
                     literal,
                     variant: None,
-                    lang,
+                    lang: None,
                     is_caption_set: false,
                 })
                 .await?;
@@ -344,7 +344,7 @@ async fn edit_msg_handler(
     bot: Bot,
     mut db: DB,
     dialogue: BotDialogue,
-    (literal, variant, lang, is_caption_set): (String, Option<String>, String, bool),
+    (literal, variant, lang, is_caption_set): (String, Option<String>, Option<String>, bool),
     msg: Message,
 ) -> BotResult<()> {
     use teloxide::utils::render::Renderer;
@@ -362,7 +362,7 @@ async fn edit_msg_handler(
         if let MediaKind::Text(text) = msg.media_kind {
             let html_text = Renderer::new(&text.text, &text.entities).as_html();
 
-            db.set_literal_alternative(&literal, &variant, &html_text)
+            db.set_literal_alternative_lang(&literal, &variant, lang.as_deref(), &html_text)
                 .await?;
             bot.send_message(chat_id, "Updated text of variant!")
                 .await?;
@@ -387,87 +387,51 @@ async fn edit_msg_handler(
                 return Ok(());
             };
             let html_text = Renderer::new(&text.text, &text.entities).as_html();
-            db.set_literal(&literal, &html_text).await?;
+            db.set_literal_lang(&literal, lang.as_deref(), &html_text)
+                .await?;
             bot.send_message(chat_id, "Updated text of message!")
                 .await?;
             dialogue.exit().await?;
         }
-        MediaKind::Photo(photo) => {
-            let group = photo.media_group_id;
-            if let Some(group) = group.clone() {
-                db.drop_media_except(&literal, &group).await?;
-            } else {
-                db.drop_media(&literal).await?;
-            }
-            let file_id = photo.photo[0].file.id.clone();
-            db.add_media(&literal, "photo", &file_id, group.as_deref())
-                .await?;
-            match photo.caption {
-                Some(text) => {
-                    let html_text = Renderer::new(&text, &photo.caption_entities).as_html();
-                    db.set_literal(&literal, &html_text).await?;
-                    bot.send_message(chat_id, "Updated photo caption!").await?;
-                }
+        kind => {
+            let media = match IncomingMedia::from_kind(kind) {
+                Some(media) => media,
                 None => {
-                    // if it is a first message in group,
-                    // or just a photo without caption (unwrap_or case),
-                    // set text empty
-                    if !db
-                        .is_media_group_exists(group.as_deref().unwrap_or(""))
-                        .await?
-                    {
-                        db.set_literal(&literal, "").await?;
-                        bot.send_message(chat_id, "Set photo without caption")
-                            .await?;
-                    };
+                    bot.send_message(chat_id, "this type of message is not supported yet")
+                        .await?;
+                    return Ok(());
                 }
-            }
-            // Some workaround because Telegram's group system
-            // is not easily and obviously handled with this
-            // code architecture, but probably there is a solution.
-            //
-            // So, this code will just wait for all media group
-            // updates to be processed
-            dialogue
-                .update(State::Edit {
-                    literal,
-                    variant: None,
-                    lang,
-                    is_caption_set: true,
-                })
-                .await?;
-            tokio::spawn(async move {
-                tokio::time::sleep(Duration::from_millis(200)).await;
-                dialogue.exit().await.unwrap_or(());
-            });
-        }
-        MediaKind::Video(video) => {
-            let group = video.media_group_id;
+            };
+            let group = media.group;
             if let Some(group) = group.clone() {
                 db.drop_media_except(&literal, &group).await?;
             } else {
                 db.drop_media(&literal).await?;
             }
-            let file_id = video.video.file.id;
-            db.add_media(&literal, "video", &file_id, group.as_deref())
+            db.add_media(&literal, media.media_type, &media.file_id, group.as_deref())
                 .await?;
-            match video.caption {
+            match media.caption {
                 Some(text) => {
-                    let html_text = Renderer::new(&text, &video.caption_entities).as_html();
-                    db.set_literal(&literal, &html_text).await?;
-                    bot.send_message(chat_id, "Updated video caption!").await?;
+                    let html_text = Renderer::new(&text, &media.caption_entities).as_html();
+                    db.set_literal_lang(&literal, lang.as_deref(), &html_text)
+                        .await?;
+                    bot.send_message(chat_id, format!("Updated {} caption!", media.media_type))
+                        .await?;
                 }
                 None => {
                     // if it is a first message in group,
-                    // or just a video without caption (unwrap_or case),
+                    // or just a media without caption (unwrap_or case),
                     // set text empty
                     if !db
                         .is_media_group_exists(group.as_deref().unwrap_or(""))
                         .await?
                     {
-                        db.set_literal(&literal, "").await?;
-                        bot.send_message(chat_id, "Set video without caption")
-                            .await?;
+                        db.set_literal_lang(&literal, lang.as_deref(), "").await?;
+                        bot.send_message(
+                            chat_id,
+                            format!("Set {} without caption", media.media_type),
+                        )
+                        .await?;
                     };
                 }
             }
@@ -490,15 +454,77 @@ async fn edit_msg_handler(
                 dialogue.exit().await.unwrap_or(());
             });
         }
-        _ => {
-            bot.send_message(chat_id, "this type of message is not supported yet")
-                .await?;
-        }
     }
 
     Ok(())
 }
 
+/// media of admin's message, that can be stored on literal
+struct IncomingMedia {
+    media_type: MediaType,
+    file_id: String,
+    caption: Option<String>,
+    caption_entities: Vec<MessageEntity>,
+    group: Option<String>,
+}
+
+impl IncomingMedia {
+    fn new(media_type: MediaType, file: &FileMeta) -> Self {
+        Self {
+            media_type,
+            file_id: file.id.to_string(),
+            caption: None,
+            caption_entities: vec![],
+            group: None,
+        }
+    }
+
+    fn with_caption(self, caption: Option<String>, caption_entities: Vec<MessageEntity>) -> Self {
+        Self {
+            caption,
+            caption_entities,
+            ..self
+        }
+    }
+
+    fn with_group(self, group: Option<String>) -> Self {
+        Self { group, ..self }
+    }
+
+    fn from_kind(kind: MediaKind) -> Option<Self> {
+        let media = match kind {
+            MediaKind::Photo(photo) => {
+                // the biggest size of photo is the last
+                Self::new(MediaType::Photo, &photo.photo.last()?.file)
+                    .with_caption(photo.caption, photo.caption_entities)
+                    .with_group(photo.media_group_id)
+            }
+            MediaKind::Video(video) => Self::new(MediaType::Video, &video.video.file)
+                .with_caption(video.caption, video.caption_entities)
+                .with_group(video.media_group_id),
+            MediaKind::Document(document) => {
+                Self::new(MediaType::Document, &document.document.file)
+                    .with_caption(document.caption, document.caption_entities)
+                    .with_group(document.media_group_id)
+            }
+            MediaKind::Animation(animation) => {
+                Self::new(MediaType::Animation, &animation.animation.file)
+                    .with_caption(animation.caption, animation.caption_entities)
+            }
+            MediaKind::Audio(audio) => Self::new(MediaType::Audio, &audio.audio.file)
+                .with_caption(audio.caption, audio.caption_entities)
+                .with_group(audio.media_group_id),
+            MediaKind::Voice(voice) => Self::new(MediaType::Voice, &voice.voice.file)
+                .with_caption(voice.caption, voice.caption_entities),
+            MediaKind::VideoNote(note) => Self::new(MediaType::VideoNote, &note.video_note.file),
+            MediaKind::Sticker(sticker) => Self::new(MediaType::Sticker, &sticker.sticker.file),
+            _ => return None,
+        };
+
+        Some(media)
+    }
+}
+
 async fn user_reply_to_support(bot: Bot, mut db: DB, msg: Message) -> BotResult<()> {
     let (source_chat_id, source_message_id) = (msg.chat.id.0, msg.id.0);
     let text = match msg.html_text() {
@@ -523,11 +549,13 @@ async fn user_reply_to_support(bot: Bot, mut db: DB, msg: Message) -> BotResult<
     let user = msg.from.ok_or(BotError::BotLogicError(
         "Unable to get user somehow:/".to_string(),
     ))?;
+    // names are set by user, so they are escaped to not break message's html
     let parts = [
         Some(user.first_name),
         user.last_name,
         user.username.map(|un| format!("(@{un})")),
-    ];
+    ]
+    .map(|part| part.map(|part| html::escape(&part)));
     #[allow(unstable_name_collisions)]
     let userformat: String = parts
         .into_iter()
@@ -536,9 +564,6 @@ async fn user_reply_to_support(bot: Bot, mut db: DB, msg: Message) -> BotResult<
         .collect();
     let msgtext = format!("From: {userformat}\nMessage:\n{text}");
 
-    // TODO: fix bug: parse mode's purpose is to display user-formated text in right way,
-    // but there is a bug: user can inject html code with his first/last/user name
-    // it's not harmful, only visible to support, but still need a fix
     let sentmsg = bot
         .send_message(ChatId(support_chat_id), msgtext)
         .parse_mode(ParseMode::Html)
@@ -0,0 +1,25 @@
+use teloxide::prelude::*;
+use teloxide::types::{ChatMemberKind, ChatMemberUpdated};
+use teloxide::{dptree, types::Update};
+
+use crate::bot_handler::BotHandler;
+use crate::db::{CallDB, DB};
+use crate::BotResult;
+
+/// tracks users, that have blocked or unblocked bot, so they are not notified
+pub fn chat_member_handler() -> BotHandler {
+    Update::filter_my_chat_member()
+        // in other chats bot was added or removed, not blocked
+        .filter(|upd: ChatMemberUpdated| upd.chat.is_private())
+        .endpoint(my_chat_member_handler)
+}
+
+async fn my_chat_member_handler(mut db: DB, upd: ChatMemberUpdated) -> BotResult<()> {
+    let blocked = matches!(
+        upd.new_chat_member.kind,
+        ChatMemberKind::Banned(_) | ChatMemberKind::Left
+    );
+    db.set_user_blocked(upd.chat.id.0, blocked).await?;
+
+    Ok(())
+}
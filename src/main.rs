@@ -2,6 +2,7 @@ pub mod admin;
 pub mod bot_handler;
 pub mod bot_manager;
 pub mod botscript;
+pub mod broadcast;
 pub mod commands;
 pub mod config;
 pub mod db;
@@ -13,15 +14,21 @@ pub mod utils;
 
 use bot_manager::BotManager;
 use botscript::application::attach_user_application;
-use botscript::{Runner, ScriptError, ScriptResult};
+use botscript::bot::attach_bot_obj;
+use botscript::{DefaultLang, Runner, ScriptError, ScriptResult};
+use config::notification::relative::RelativeNotifications;
 use config::result::ConfigError;
 use config::{Provider, RunnerConfig};
 use db::application::Application;
 use db::bots::BotInstance;
 use db::callback_info::CallbackInfo;
+use db::notification_delivery::NotificationDelivery;
+use db::scheduled_message::ScheduledMessage;
 use handlers::admin::admin_handler;
+use handlers::chat_member::chat_member_handler;
 use log::{error, info};
 use message_answerer::MessageAnswererError;
+use runtimes::v8::V8Runtime;
 use std::sync::{Arc, Mutex};
 
 use crate::db::{CallDB, DB};
@@ -33,6 +40,9 @@ use serde::{Deserialize, Serialize};
 use teloxide::dispatching::dialogue::serializer::Json;
 use teloxide::dispatching::dialogue::Serializer;
 use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::utils::html;
+use utils::template::{self, TemplateContext};
 
 type BotDialogue = Dialogue<State, MongodbStorage<Json>>;
 
@@ -68,11 +78,16 @@ pub enum State {
     Edit {
         literal: String,
         variant: Option<String>,
-        lang: String,
+        /// language of literal's value, `None` to set value for any language
+        lang: Option<String>,
         is_caption_set: bool,
     },
     EditButton,
     MessageForwardReply,
+    /// state set by script, user's messages are handled by `stateful_msg_handlers`
+    Script {
+        state: String,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -89,19 +104,18 @@ pub enum Callback {
 type CallbackStore = CallbackInfo<Callback>;
 
 #[derive(Clone)]
-pub struct BotController {
+pub struct BotController<P: Provider> {
     pub bot: Bot,
     pub db: DB,
-    pub runtime: Arc<Mutex<BotRuntime>>,
+    pub runtime: Arc<Mutex<BotRuntime<P>>>,
 }
 
 pub struct BotRuntime<P: Provider> {
     pub rc: RunnerConfig<P>,
-    pub runner: Runner,
+    pub runner: Runner<P>,
 }
-unsafe impl<P: Provider> Send for BotRuntime<P> {}
 
-impl Drop for BotController {
+impl<P: Provider> Drop for BotController<P> {
     fn drop(&mut self) {
         info!("called drop for BotController");
     }
@@ -109,7 +123,7 @@ impl Drop for BotController {
 
 const MAIN_BOT_SCRIPT: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/mainbot.js"));
 
-impl BotController {
+impl BotController<V8Runtime> {
     pub async fn new(config: &Config) -> ScriptResult<Self> {
         Self::create(
             &config.bot_token,
@@ -125,13 +139,29 @@ impl BotController {
 
         Self::with_db(db, token, script).await
     }
+}
 
+impl<P> BotController<P>
+where
+    P: Provider + Default,
+    P::InitData: From<String>,
+{
     pub async fn with_db(mut db: DB, token: &str, script: &str) -> ScriptResult<Self> {
         let bot = Bot::new(token);
 
         let mut runner = Runner::init_with_db(&mut db)?;
-        // runner.call_attacher(|c, o| attach_user_application(c, o, db.clone(), bot.clone()))??;
+        let relative = RelativeNotifications::default();
+        let default_lang = DefaultLang::default();
+        runner.call_attacher(|host| {
+            attach_user_application(host, &db, &bot, &relative, &default_lang)
+        })?;
+        runner.call_attacher(|host| attach_bot_obj(host, &db, &bot, &default_lang))?;
         let rc = runner.init_config(script)?;
+        relative.set(rc.relative_notifications());
+        default_lang.set(rc.default_language());
+        db.set_callback_ttl(rc.callback_ttl()).await?;
+        ScheduledMessage::create_indexes(&mut db).await?;
+        NotificationDelivery::create_indexes(&mut db).await?;
         let runtime = Arc::new(Mutex::new(BotRuntime { rc, runner }));
 
         Ok(Self { bot, db, runtime })
@@ -189,7 +219,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             BotInstance::restart_all(&mut db, false).await.unwrap();
             std::iter::once(bi).chain(instances)
         },
-        async |_| vec![admin_handler()].into_iter(),
+        async |_| vec![chat_member_handler(), admin_handler()].into_iter(),
     );
 
     bm.dispatch(&mut db).await?;
@@ -225,17 +255,17 @@ async fn send_application_to_chat(
             ));
         }
     };
-    let msg = match db.get_literal_value("application_format").await? {
-        Some(msg) => msg
-            .replace("{user_id}", app.from.id.0.to_string().as_str())
-            .replace(
-                "{username}",
-                app.from
-                    .username
-                    .clone()
-                    .unwrap_or("Username not set".to_string())
-                    .as_str(),
-            ),
+    let format = match db.get_literal_value("application_format").await? {
+        // `{user_id}` and `{username}` are kept for formats set before templates
+        Some(format) => {
+            let username = match app.from.username {
+                Some(_) => "{user.username}".to_string(),
+                None => html::escape("Username not set"),
+            };
+            format
+                .replace("{user_id}", "{user.id}")
+                .replace("{username}", &username)
+        }
         None => {
             notify_admin("format for support_chat_id is not set").await;
             return Err(BotError::AdminMisconfiguration(
@@ -243,8 +273,13 @@ async fn send_application_to_chat(
             ));
         }
     };
+    let ctx = TemplateContext::default().with_user(&app.from);
+    let msg = template::render(&format, &ctx, db).await?;
 
-    Ok(bot.send_message(ChatId(chat_id), msg).await?)
+    Ok(bot
+        .send_message(ChatId(chat_id), msg)
+        .parse_mode(ParseMode::Html)
+        .await?)
 }
 
 /// This is an emergent situation function, so it should not return any Result, but handle Results
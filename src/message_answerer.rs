@@ -1,17 +1,19 @@
 use log::{info, warn};
 use teloxide::prelude::*;
 use teloxide::types::{
-    InputFile, InputMedia, InputMediaPhoto, InputMediaVideo, MessageId, ParseMode,
+    InputFile, InputMedia, InputMediaAnimation, InputMediaAudio, InputMediaDocument,
+    InputMediaPhoto, InputMediaVideo, MessageId, ParseMode,
 };
 use teloxide::{
-    types::{ChatId, InlineKeyboardMarkup},
+    types::{ChatId, ReplyMarkup},
     Bot,
 };
 
-use crate::db::{DbError, DbResult, Media};
+use crate::db::{DbError, DbResult, Langs, Media, MediaType};
 use crate::{
     db::{CallDB, DB},
     notify_admin,
+    utils::template::{self, TemplateContext, TemplateUser},
 };
 
 macro_rules! send_media {
@@ -30,14 +32,40 @@ macro_rules! send_media {
         let msg = msg.parse_mode(teloxide::types::ParseMode::Html);
 
         let msg = msg.await?;
-        Ok((msg.chat.id.0, msg.id.0))
+        Ok(vec![msg.id.0])
     }};
 }
 
+/// the same as `send_media`, but for media that can't have caption
+macro_rules! send_captionless_media {
+    ($self:ident, $method:ident, $chat_id:expr, $file_id: expr, $keyboard: expr) => {{
+        let msg = $self
+            .bot
+            .$method(ChatId($chat_id), InputFile::file_id($file_id.to_string()));
+        let msg = match $keyboard {
+            Some(kbd) => msg.reply_markup(kbd),
+            None => msg,
+        };
+
+        msg.await?
+    }};
+}
+
+/// suffix of literal with text of message with keyboard, sent after album,
+/// e.g. `catalog__kbd` for `catalog`
+const ALBUM_KEYBOARD_SUFFIX: &str = "__kbd";
+/// text of message with album's keyboard, if its literal is not set
+const ALBUM_KEYBOARD_TEXT: &str = "⬆️";
+
 pub struct MessageAnswerer<'a> {
     bot: &'a Bot,
     chat_id: i64,
     db: &'a mut DB,
+    context: TemplateContext,
+    /// languages of literals, user's language if not set
+    langs: Option<Langs>,
+    /// language of bot's config, used after user's one, if `langs` are not set
+    default_lang: Option<String>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -46,13 +74,65 @@ pub enum MessageAnswererError {
     DbError(#[from] DbError),
     #[error("Failed teloxide request: {0:?}")]
     RequestError(#[from] teloxide::RequestError),
+    #[error("Media of type `{0}` can't be sent this way")]
+    UnsupportedMedia(MediaType),
 }
 
 pub type MAResult<T> = Result<T, MessageAnswererError>;
 
 impl<'a> MessageAnswerer<'a> {
     pub fn new(bot: &'a Bot, db: &'a mut DB, chat_id: i64) -> Self {
-        Self { bot, chat_id, db }
+        Self {
+            bot,
+            chat_id,
+            db,
+            context: Default::default(),
+            langs: None,
+            default_lang: None,
+        }
+    }
+
+    pub fn with_default_lang(self, default_lang: Option<String>) -> Self {
+        Self {
+            default_lang,
+            ..self
+        }
+    }
+
+    pub fn with_langs(self, langs: Langs) -> Self {
+        Self {
+            langs: Some(langs),
+            ..self
+        }
+    }
+
+    async fn langs(&mut self) -> DbResult<Langs> {
+        if let Some(ref langs) = self.langs {
+            return Ok(langs.clone());
+        }
+        // messages are sent to private chats, so chat is the user
+        let langs = Langs::of_user(self.db, self.chat_id, self.default_lang.as_deref()).await?;
+        self.langs = Some(langs.clone());
+
+        Ok(langs)
+    }
+
+    /// values of placeholders in literals, see [`crate::utils::template`]
+    pub fn with_context(self, context: TemplateContext) -> Self {
+        Self { context, ..self }
+    }
+
+    async fn render(&mut self, text: &str, variant: Option<&str>) -> DbResult<String> {
+        let mut ctx = self.context.clone();
+        ctx.variant = ctx.variant.or(variant.map(str::to_string));
+        ctx.langs = self.langs().await?;
+        // messages are sent to private chats, so chat is the user
+        if ctx.user.is_none() && template::uses_user(text) {
+            let users = self.db.get_users_by_ids(vec![self.chat_id]).await?;
+            ctx.user = users.first().map(TemplateUser::from);
+        }
+
+        template::render(text, &ctx, self.db).await
     }
 
     async fn get_text(
@@ -61,11 +141,12 @@ impl<'a> MessageAnswerer<'a> {
         variant: Option<&str>,
         is_replace: bool,
     ) -> DbResult<String> {
+        let langs = self.langs().await?;
         let variant_text = match variant {
             Some(variant) => {
                 let value = self
                     .db
-                    .get_literal_alternative_value(literal, variant)
+                    .get_literal_alternative_value_lang(literal, variant, &langs)
                     .await?;
                 if value.is_none() && !is_replace {
                     notify_admin(&format!("variant {variant} for literal {literal} is not found! falling back to just literal")).await;
@@ -78,19 +159,19 @@ impl<'a> MessageAnswerer<'a> {
             Some(text) => text,
             None => self
                 .db
-                .get_literal_value(literal)
+                .get_literal_value_lang(literal, &langs)
                 .await?
                 .unwrap_or("Please, set content of this message".into()),
         };
 
-        Ok(text)
+        self.render(&text, variant).await
     }
 
     pub async fn answer(
         mut self,
         literal: &str,
         variant: Option<&str>,
-        keyboard: Option<InlineKeyboardMarkup>,
+        keyboard: Option<ReplyMarkup>,
     ) -> MAResult<(i64, i32)> {
         let text = self.get_text(literal, variant, false).await?;
         self.answer_inner(text, literal, variant, keyboard).await
@@ -99,7 +180,7 @@ impl<'a> MessageAnswerer<'a> {
     pub async fn answer_text(
         self,
         text: String,
-        keyboard: Option<InlineKeyboardMarkup>,
+        keyboard: Option<ReplyMarkup>,
     ) -> MAResult<(i64, i32)> {
         self.send_message(text, keyboard)
             .await
@@ -111,33 +192,85 @@ impl<'a> MessageAnswerer<'a> {
         text: String,
         literal: &str,
         variant: Option<&str>,
-        keyboard: Option<InlineKeyboardMarkup>,
+        keyboard: Option<ReplyMarkup>,
     ) -> MAResult<(i64, i32)> {
         let media = self.db.get_media(literal).await?;
-        let (chat_id, msg_id) = match media.len() {
+        let ids = match media.len() {
             // just a text
-            0 => self.send_message(text, keyboard).await?,
-            // single media
+            0 => vec![self.send_message(text, keyboard).await?.1],
+            // single media, maybe with text as a separate message
             1 => self.send_media(&media[0], text, keyboard).await?,
             // >= 2, should use media group
-            _ => self.send_media_group(media, text).await?,
+            _ => {
+                return self
+                    .send_album(media, text, literal, variant, keyboard)
+                    .await
+            }
         };
-        self.store_message_info(msg_id, literal, variant).await?;
-        Ok((chat_id, msg_id))
+        self.store_messages(&ids, literal, variant).await?;
+
+        let msg_id = *ids.last().expect("at least one message is sent");
+        Ok((self.chat_id, msg_id))
+    }
+
+    /// sends media group with keyboard as a separate message after it, since albums can't
+    /// have reply markup. Returns id of the last sent message
+    async fn send_album(
+        &mut self,
+        media: Vec<Media>,
+        text: String,
+        literal: &str,
+        variant: Option<&str>,
+        keyboard: Option<ReplyMarkup>,
+    ) -> MAResult<(i64, i32)> {
+        let mut ids = self.send_media_group(media, text).await?;
+        if let Some(keyboard) = keyboard {
+            let langs = self.langs().await?;
+            let kbd_text = self
+                .db
+                .get_literal_value_lang(&format!("{literal}{ALBUM_KEYBOARD_SUFFIX}"), &langs)
+                .await?
+                .unwrap_or(ALBUM_KEYBOARD_TEXT.to_string());
+            let kbd_text = self.render(&kbd_text, variant).await?;
+            let (_, msg_id) = self.send_message(kbd_text, Some(keyboard)).await?;
+            ids.push(msg_id);
+        }
+
+        self.store_messages(&ids, literal, variant).await?;
+
+        let msg_id = *ids.last().expect("media group can't be empty");
+        Ok((self.chat_id, msg_id))
     }
 
     pub async fn replace_message(
         mut self,
         message_id: i32,
         literal: &str,
-        keyboard: Option<InlineKeyboardMarkup>,
+        keyboard: Option<ReplyMarkup>,
     ) -> MAResult<()> {
-        let variant = self
-            .db
-            .get_message(self.chat_id, message_id)
-            .await?
-            .and_then(|m| m.variant);
+        let (variant, group) = match self.db.get_message(self.chat_id, message_id).await? {
+            Some(msg) => (msg.variant, msg.group_message_ids),
+            None => (None, vec![]),
+        };
         let text = self.get_text(literal, variant.as_deref(), true).await?;
+        // only inline keyboard can be attached to edited message
+        let keyboard = match keyboard {
+            Some(ReplyMarkup::InlineKeyboard(kbd)) => Some(kbd),
+            None => None,
+            Some(markup) => {
+                self.answer_inner(text, literal, variant.as_deref(), Some(markup))
+                    .await?;
+                return Ok(());
+            }
+        };
+        // albums can't be edited, so the whole album is replaced with new messages
+        if !group.is_empty() {
+            let ids: Vec<i32> = group.into_iter().map(|id| id as i32).collect();
+            let keyboard = keyboard.map(ReplyMarkup::InlineKeyboard);
+            return self
+                .resend(&ids, text, literal, variant.as_deref(), keyboard)
+                .await;
+        }
         let media = self.db.get_media(literal).await?;
         let (_, msg_id) = match media.len() {
             // just a text
@@ -159,6 +292,7 @@ impl<'a> MessageAnswerer<'a> {
                     {
                         // fallback to sending message
                         warn!("Fallback into sending message instead of editing because it contains media");
+                        let keyboard = keyboard.map(ReplyMarkup::InlineKeyboard);
                         self.answer_inner(text, literal, variant.as_deref(), keyboard)
                             .await?;
                         return Ok(());
@@ -170,12 +304,15 @@ impl<'a> MessageAnswerer<'a> {
             }
             // single media
             1 => {
-                let media = &media[0]; // safe, cause we just checked len
-                let input_file = InputFile::file_id(media.file_id.to_string());
-                let media = match media.media_type.as_str() {
-                    "photo" => InputMedia::Photo(teloxide::types::InputMediaPhoto::new(input_file)),
-                    "video" => InputMedia::Video(teloxide::types::InputMediaVideo::new(input_file)),
-                    _ => todo!(),
+                let media = match input_media(&media[0], None) {
+                    Some(media) => media,
+                    // voices, video notes and stickers can't be edited
+                    None => {
+                        let keyboard = keyboard.map(ReplyMarkup::InlineKeyboard);
+                        return self
+                            .resend(&[message_id], text, literal, variant.as_deref(), keyboard)
+                            .await;
+                    }
                 };
                 self.bot
                     .edit_message_media(ChatId(self.chat_id), MessageId(message_id), media)
@@ -198,9 +335,12 @@ impl<'a> MessageAnswerer<'a> {
 
                 (msg.chat.id.0, msg.id.0)
             }
-            // >= 2, should use media group
+            // >= 2, message can't be edited into album
             _ => {
-                todo!();
+                let keyboard = keyboard.map(ReplyMarkup::InlineKeyboard);
+                return self
+                    .resend(&[message_id], text, literal, variant.as_deref(), keyboard)
+                    .await;
             }
         };
 
@@ -210,6 +350,108 @@ impl<'a> MessageAnswerer<'a> {
         Ok(())
     }
 
+    /// deletes messages and sends new ones instead of them
+    async fn resend(
+        mut self,
+        message_ids: &[i32],
+        text: String,
+        literal: &str,
+        variant: Option<&str>,
+        keyboard: Option<ReplyMarkup>,
+    ) -> MAResult<()> {
+        self.delete_messages(message_ids).await?;
+
+        self.answer_inner(text, literal, variant, keyboard).await?;
+        Ok(())
+    }
+
+    /// deletes message, together with the rest of album and its keyboard, if message is a part
+    /// of it
+    pub async fn delete(mut self, message_id: i32) -> MAResult<()> {
+        let group = self
+            .db
+            .get_message(self.chat_id, message_id)
+            .await?
+            .map(|m| m.group_message_ids)
+            .unwrap_or_default();
+        let ids = match group.is_empty() {
+            true => vec![message_id],
+            false => group.into_iter().map(|id| id as i32).collect(),
+        };
+
+        self.delete_messages(&ids).await
+    }
+
+    async fn delete_messages(&mut self, message_ids: &[i32]) -> MAResult<()> {
+        let delete = self.bot.delete_messages(
+            ChatId(self.chat_id),
+            message_ids.iter().map(|id| MessageId(*id)),
+        );
+        if let Err(err) = delete.await {
+            // messages older than 48 hours can't be deleted, so they are left in chat
+            warn!("Failed to delete messages {message_ids:?}, err: {err}");
+        }
+        self.db.drop_messages(self.chat_id, message_ids).await?;
+
+        Ok(())
+    }
+
+    /// replaces text of message, sending new one if message can't be edited
+    pub async fn replace_text(
+        self,
+        message_id: i32,
+        text: String,
+        keyboard: Option<ReplyMarkup>,
+    ) -> MAResult<()> {
+        // only inline keyboard can be attached to edited message
+        let keyboard = match keyboard {
+            Some(ReplyMarkup::InlineKeyboard(kbd)) => Some(kbd),
+            None => None,
+            Some(markup) => {
+                self.send_message(text, Some(markup)).await?;
+                return Ok(());
+            }
+        };
+        let msg = self
+            .bot
+            .edit_message_text(ChatId(self.chat_id), MessageId(message_id), &text);
+        let msg = match keyboard {
+            Some(ref kbd) => msg.reply_markup(kbd.clone()),
+            None => msg,
+        };
+        let msg = msg.parse_mode(teloxide::types::ParseMode::Html);
+        match msg.await {
+            Ok(_) => Ok(()),
+            Err(teloxide::RequestError::Api(teloxide::ApiError::Unknown(errtext)))
+                if errtext.as_str() == "Bad Request: there is no text in the message to edit" =>
+            {
+                warn!("Fallback into sending message instead of editing because it contains media");
+                let keyboard = keyboard.map(ReplyMarkup::InlineKeyboard);
+                self.send_message(text, keyboard).await?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// stores sent messages, grouping them if there are several, so they are replaced and
+    /// deleted together
+    async fn store_messages(
+        &mut self,
+        message_ids: &[i32],
+        literal: &str,
+        variant: Option<&str>,
+    ) -> DbResult<()> {
+        for msg_id in message_ids {
+            self.store_message_info(*msg_id, literal, variant).await?;
+        }
+        if message_ids.len() > 1 {
+            self.db.set_message_group(self.chat_id, message_ids).await?;
+        }
+
+        Ok(())
+    }
+
     async fn store_message_info(
         &mut self,
         message_id: i32,
@@ -235,7 +477,7 @@ impl<'a> MessageAnswerer<'a> {
     async fn send_message(
         &self,
         text: String,
-        keyboard: Option<InlineKeyboardMarkup>,
+        keyboard: Option<ReplyMarkup>,
     ) -> Result<(i64, i32), teloxide::RequestError> {
         let msg = self.bot.send_message(ChatId(self.chat_id), text);
         let msg = match keyboard {
@@ -253,45 +495,77 @@ impl<'a> MessageAnswerer<'a> {
         &self,
         media: &Media,
         text: String,
-        keyboard: Option<InlineKeyboardMarkup>,
-    ) -> Result<(i64, i32), teloxide::RequestError> {
-        match media.media_type.as_str() {
-            "photo" => {
-                send_media!(
-                    self,
-                    send_photo,
-                    self.chat_id,
-                    media.file_id,
-                    text,
-                    keyboard
-                )
+        keyboard: Option<ReplyMarkup>,
+    ) -> Result<Vec<i32>, teloxide::RequestError> {
+        let file_id = &media.file_id;
+        match media.media_type {
+            MediaType::Photo => {
+                send_media!(self, send_photo, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::Video => {
+                send_media!(self, send_video, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::Document => {
+                send_media!(self, send_document, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::Animation => {
+                send_media!(self, send_animation, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::Audio => {
+                send_media!(self, send_audio, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::Voice => {
+                send_media!(self, send_voice, self.chat_id, file_id, text, keyboard)
+            }
+            MediaType::VideoNote | MediaType::Sticker => {
+                self.send_captionless_media(media, text, keyboard).await
+            }
+        }
+    }
+
+    /// sends media, that can't have a caption, with text as a separate message after it.
+    /// Returns ids of all sent messages
+    async fn send_captionless_media(
+        &self,
+        media: &Media,
+        text: String,
+        keyboard: Option<ReplyMarkup>,
+    ) -> Result<Vec<i32>, teloxide::RequestError> {
+        // keyboard is attached to the last message
+        let (media_keyboard, text_keyboard) = match text.as_str() {
+            "" => (keyboard, None),
+            _ => (None, keyboard),
+        };
+        let file_id = &media.file_id;
+        let msg = match media.media_type {
+            MediaType::Sticker => {
+                send_captionless_media!(self, send_sticker, self.chat_id, file_id, media_keyboard)
             }
-            "video" => {
-                send_media!(
+            _ => {
+                send_captionless_media!(
                     self,
-                    send_video,
+                    send_video_note,
                     self.chat_id,
-                    media.file_id,
-                    text,
-                    keyboard
+                    file_id,
+                    media_keyboard
                 )
             }
+        };
+
+        match text.as_str() {
+            "" => Ok(vec![msg.id.0]),
             _ => {
-                todo!()
+                let (_, text_id) = self.send_message(text, text_keyboard).await?;
+                Ok(vec![msg.id.0, text_id])
             }
         }
     }
 
-    async fn send_media_group(
-        &self,
-        media: Vec<Media>,
-        text: String,
-    ) -> Result<(i64, i32), teloxide::RequestError> {
+    async fn send_media_group(&self, media: Vec<Media>, text: String) -> MAResult<Vec<i32>> {
         let media: Vec<InputMedia> = media
-            .into_iter()
+            .iter()
             .enumerate()
             .map(|(i, m)| {
-                let ifile = InputFile::file_id(m.file_id);
                 let caption = if i == 0 {
                     match text.as_str() {
                         "" => None,
@@ -300,27 +574,55 @@ impl<'a> MessageAnswerer<'a> {
                 } else {
                     None
                 };
-                match m.media_type.as_str() {
-                    "photo" => InputMedia::Photo(InputMediaPhoto {
-                        caption,
-                        parse_mode: Some(ParseMode::Html),
-                        ..InputMediaPhoto::new(ifile)
-                    }),
-                    "video" => InputMedia::Video(InputMediaVideo {
-                        caption,
-                        parse_mode: Some(ParseMode::Html),
-                        ..InputMediaVideo::new(ifile)
-                    }),
-                    _ => {
-                        todo!()
-                    }
-                }
+                // albums can't contain animations, though message can be edited to one
+                let media = match m.media_type {
+                    MediaType::Animation => None,
+                    _ => input_media(m, caption),
+                };
+                media.ok_or(MessageAnswererError::UnsupportedMedia(m.media_type))
             })
-            .collect();
+            .collect::<Result<_, _>>()?;
         let msg = self.bot.send_media_group(ChatId(self.chat_id), media);
 
         let msg = msg.await?;
 
-        Ok((msg[0].chat.id.0, msg[0].id.0))
+        Ok(msg.iter().map(|m| m.id.0).collect())
     }
 }
+
+/// media as a part of media group or edited message,
+/// `None` if telegram doesn't support this type there
+fn input_media(media: &Media, caption: Option<String>) -> Option<InputMedia> {
+    let ifile = InputFile::file_id(media.file_id.to_string());
+    let parse_mode = Some(ParseMode::Html);
+    let media = match media.media_type {
+        MediaType::Photo => InputMedia::Photo(InputMediaPhoto {
+            caption,
+            parse_mode,
+            ..InputMediaPhoto::new(ifile)
+        }),
+        MediaType::Video => InputMedia::Video(InputMediaVideo {
+            caption,
+            parse_mode,
+            ..InputMediaVideo::new(ifile)
+        }),
+        MediaType::Document => InputMedia::Document(InputMediaDocument {
+            caption,
+            parse_mode,
+            ..InputMediaDocument::new(ifile)
+        }),
+        MediaType::Animation => InputMedia::Animation(InputMediaAnimation {
+            caption,
+            parse_mode,
+            ..InputMediaAnimation::new(ifile)
+        }),
+        MediaType::Audio => InputMedia::Audio(InputMediaAudio {
+            caption,
+            parse_mode,
+            ..InputMediaAudio::new(ifile)
+        }),
+        MediaType::Voice | MediaType::VideoNote | MediaType::Sticker => return None,
+    };
+
+    Some(media)
+}
@@ -1,33 +1,406 @@
-use mlua::{Error, Function, Lua, Value};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use crate::config::Provider;
+use async_trait::async_trait;
+use mlua::{Error, Function, Lua, LuaSerdeExt, MultiValue, SerializeOptions, Table, Value};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::botscript::host::{HostApi, HostFunction};
+use crate::config::{
+    traits::{ProviderCall, ProviderDeserialize, ProviderSerialize},
+    Provider, RunnerConfig,
+};
+
+/// key of table, that is put in place of function
+const FUNCTION_KEY: &str = "$function";
+
+/// tables nested deeper are left as is, mostly to not hang on cyclic references
+const MAX_DEPTH: usize = 64;
+
+thread_local! {
+    /// Runtime that deserialized functions are bound to.
+    /// Set only for the time of deserialization, see [`LuaRuntime::with_deserialize`]
+    static DESERIALIZE_RUNTIME: RefCell<Option<LuaRuntime>> = const { RefCell::new(None) };
+}
+
+fn deserialize_runtime() -> LuaRuntime {
+    DESERIALIZE_RUNTIME
+        .with_borrow(|runtime| runtime.clone())
+        .unwrap_or_default()
+}
 
 #[derive(Clone)]
 pub struct LuaRuntime {
     lua: Lua,
+    functions: Arc<Mutex<HashMap<String, Function>>>,
+}
+
+impl Default for LuaRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LuaRuntime {
     pub fn new() -> Self {
         let lua = Lua::new();
-        Self { lua }
+        Self {
+            lua,
+            functions: Default::default(),
+        }
+    }
+
+    /// deserialized inside of `f` functions will be bound to this runtime
+    fn with_deserialize<T>(&self, f: impl FnOnce() -> T) -> T {
+        let prev = DESERIALIZE_RUNTIME.replace(Some(self.clone()));
+        let result = f();
+        DESERIALIZE_RUNTIME.set(prev);
+
+        result
     }
+
+    fn get_function(&self, key: &str) -> Result<Function, Error> {
+        let functions = self
+            .functions
+            .lock()
+            .map_err(|err| Error::external(format!("functions lock is poisoned: {err}")))?;
+
+        functions
+            .get(key)
+            .cloned()
+            .ok_or_else(|| Error::external(format!("function `{key}` is not found in runtime")))
+    }
+
+    /// stores function in table, reusing key if the same function was already stored
+    fn store_function(&self, f: Function) -> Result<String, Error> {
+        let mut functions = self
+            .functions
+            .lock()
+            .map_err(|err| Error::external(format!("functions lock is poisoned: {err}")))?;
+
+        if let Some((key, _)) = functions.iter().find(|(_, stored)| **stored == f) {
+            return Ok(key.clone());
+        }
+
+        let key = format!("function#{}", functions.len());
+        functions.insert(key.clone(), f);
+        Ok(key)
+    }
+
+    /// move out unsupported by serde types to function table,
+    /// leaving table `{["$function"] = key}` instead of value to get this value
+    fn replace(&self, value: Value, depth: usize) -> Result<Value, Error> {
+        match value {
+            Value::Function(f) => {
+                let key = self.store_function(f)?;
+                let placeholder = self.lua.create_table()?;
+                placeholder.set(FUNCTION_KEY, key)?;
+
+                Ok(Value::Table(placeholder))
+            }
+            Value::Table(table) if depth <= MAX_DEPTH => {
+                let out = self.lua.create_table()?;
+                for pair in table.pairs::<Value, Value>() {
+                    let (key, value) = pair?;
+                    out.raw_set(key, self.replace(value, depth + 1)?)?;
+                }
+
+                Ok(Value::Table(out))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn lua_to_json(&self, value: Value) -> Result<serde_json::Value, Error> {
+        let value = self.replace(value, 0)?;
+
+        self.lua.from_value(value)
+    }
+
+    fn json_to_lua(&self, value: &serde_json::Value) -> Result<Value, Error> {
+        json_to_lua(&self.lua, value)
+    }
+
+    fn json_args(&self, args: &[&LuaValue]) -> Result<MultiValue, Error> {
+        args.iter()
+            .map(|arg| self.json_to_lua(&arg.value))
+            .collect()
+    }
+
+    fn lua_result(&self, result: Value) -> Result<Option<LuaValue>, Error> {
+        if result.is_nil() {
+            return Ok(None);
+        }
+
+        Ok(Some(LuaValue {
+            value: self.lua_to_json(result)?,
+            runtime: Some(self.clone()),
+        }))
+    }
+
+    fn create_host_function(&self, f: HostFunction) -> Result<Function, Error> {
+        match f {
+            HostFunction::Sync(f) => self.lua.create_function(move |lua, args: MultiValue| {
+                let result = f(lua_args(lua, args)?).map_err(Error::external)?;
+
+                json_to_lua(lua, &result)
+            }),
+            // yields current coroutine until future is ready,
+            // so function is called from script as a regular one
+            HostFunction::Async(f) => {
+                self.lua
+                    .create_async_function(move |lua, args: MultiValue| {
+                        let f = f.clone();
+                        async move {
+                            let result = f(lua_args(&lua, args)?).await.map_err(Error::external)?;
+
+                            json_to_lua(&lua, &result)
+                        }
+                    })
+            }
+        }
+    }
+
+    /// global table with given name, created if it is not set yet
+    fn global_table(&self, name: &str) -> Result<Table, Error> {
+        let globals = self.lua.globals();
+        if let Some(table) = globals.get::<Option<Table>>(name)? {
+            return Ok(table);
+        }
+
+        let table = self.lua.create_table()?;
+        globals.set(name, &table)?;
+        Ok(table)
+    }
+}
+
+fn lua_args(lua: &Lua, args: MultiValue) -> Result<Vec<serde_json::Value>, Error> {
+    args.into_iter().map(|arg| lua.from_value(arg)).collect()
+}
+
+fn json_to_lua(lua: &Lua, value: &serde_json::Value) -> Result<Value, Error> {
+    // passing `null` as `nil`, since scripts are checking exactly for it
+    let options = SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+
+    lua.to_value_with(value, options)
 }
 
 pub struct LuaInit {
     config: String,
 }
 
+impl From<String> for LuaInit {
+    fn from(config: String) -> Self {
+        Self { config }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LuaValue {
+    value: serde_json::Value,
+    /// runtime this value came from, needed to resolve functions inside of value
+    #[serde(skip)]
+    runtime: Option<LuaRuntime>,
+}
+
+impl ProviderDeserialize for LuaValue {
+    type Provider = LuaRuntime;
+
+    fn de_into<T: DeserializeOwned>(&self) -> Result<T, <Self::Provider as Provider>::Error> {
+        let de = || serde_json::from_value(self.value.clone());
+        let value = match &self.runtime {
+            Some(runtime) => runtime.with_deserialize(de),
+            None => de(),
+        }
+        .map_err(Error::external)?;
+
+        Ok(value)
+    }
+}
+
+impl ProviderSerialize for LuaValue {
+    type Provider = LuaRuntime;
+
+    fn se_from<T: Serialize>(from: &T) -> Result<Self, <Self::Provider as Provider>::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            value: serde_json::to_value(from).map_err(Error::external)?,
+            runtime: None,
+        })
+    }
+}
+
+impl std::fmt::Debug for LuaValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LuaValue")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LuaFunction {
+    #[serde(rename = "$function")]
+    key: String,
+    #[serde(skip, default = "deserialize_runtime")]
+    runtime: LuaRuntime,
+}
+
+#[async_trait]
+impl ProviderCall for LuaFunction {
+    type Provider = LuaRuntime;
+
+    async fn call_async(
+        &self,
+        args: &[&<Self::Provider as Provider>::Value],
+    ) -> Result<Option<<Self::Provider as Provider>::Value>, <Self::Provider as Provider>::Error>
+    {
+        let f = self.runtime.get_function(&self.key)?;
+        let args = self.runtime.json_args(args)?;
+
+        let result: Value = f.call_async(args).await?;
+        self.runtime.lua_result(result)
+    }
+}
+
+impl std::fmt::Debug for LuaFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LuaFunction")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
 impl Provider for LuaRuntime {
-    type Function = Function;
+    type Function = LuaFunction;
 
-    type Value = Value;
+    type Value = LuaValue;
 
     type Error = Error;
 
     type InitData = LuaInit;
 
-    fn init_config(&self, d: Self::InitData) -> Result<crate::config::RunnerConfig<Self>, Self::Error> {
-        todo!()
+    fn attach_host(&self, host: HostApi) -> Result<(), Self::Error> {
+        for (object, name, f) in host.entries() {
+            let function = self.create_host_function(f.clone())?;
+
+            match object {
+                Some(object) => self.global_table(object)?.set(name, function)?,
+                None => self.lua.globals().set(name, function)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn init_config(&self, d: Self::InitData) -> Result<RunnerConfig<Self>, Self::Error> {
+        let value: Value = self.lua.load(d.config.as_str()).set_name("script").eval()?;
+        let config = self.lua_to_json(value)?;
+        let config = self
+            .with_deserialize(|| serde_json::from_value(config))
+            .map_err(Error::external)?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::botscript::host::arg;
+
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        local function cancel_buttons() return "cancelation" end
+        local function sum(a, b) return a + b end
+
+        return {
+            config = { version = 1.0 },
+            dialog = {
+                commands = {
+                    start = { handler = cancel_buttons },
+                    sum = { handler = sum },
+                },
+                buttons = {},
+                stateful_msg_handlers = {},
+            },
+        }
+    "#;
+
+    fn init() -> RunnerConfig<LuaRuntime> {
+        let runtime = LuaRuntime::new();
+        runtime
+            .init_config(LuaInit::from(SCRIPT.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_init_config_function() {
+        let rc = init();
+        let handler = rc.get_command_message("start").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let res = handler.call_async().await.unwrap().unwrap();
+        let sres: String = res.de_into().unwrap();
+        assert_eq!(sres, "cancelation");
+    }
+
+    #[tokio::test]
+    async fn test_function_args() {
+        let rc = init();
+        let handler = rc.get_command_message("sum").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let a = LuaValue::se_from(&40).unwrap();
+        let b = LuaValue::se_from(&2).unwrap();
+        let res = handler.call_args_async(&[&a, &b]).await.unwrap().unwrap();
+        let res: f64 = res.de_into().unwrap();
+        assert_eq!(res, 42.0);
+    }
+
+    #[test]
+    fn test_init_config_invalid() {
+        let runtime = LuaRuntime::new();
+        let result = runtime.init_config(LuaInit::from("invalid_script(".to_string()));
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_host_function() {
+        let runtime = LuaRuntime::new();
+        let mut host = HostApi::new();
+        host.set_async_function("double", |args| async move {
+            let n: f64 = arg(&args, 0)?;
+            Ok(serde_json::Value::from(n * 2.0))
+        });
+        runtime.attach_host(host).unwrap();
+
+        let script = r#"
+            local function doubled(a) return double(a) end
+            return {
+                config = { version = 1.0 },
+                dialog = { commands = { double = { handler = doubled } }, buttons = {}, stateful_msg_handlers = {} },
+            }
+        "#;
+        let rc = runtime
+            .init_config(LuaInit::from(script.to_string()))
+            .unwrap();
+        let handler = rc.get_command_message("double").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let a = LuaValue::se_from(&21).unwrap();
+        let res = handler.call_args_async(&[&a]).await.unwrap().unwrap();
+        let res: f64 = res.de_into().unwrap();
+        assert_eq!(res, 42.0);
     }
 }
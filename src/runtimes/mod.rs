@@ -1,2 +1,4 @@
+pub mod mlua;
+#[cfg(test)]
+pub mod testing;
 pub mod v8;
-// pub mod mlua;
@@ -0,0 +1,123 @@
+//! Provider without script engine, to test config logic independently of runtimes.
+//!
+//! Config is a json, where functions are set as `{"$function": "name"}` and
+//! should be registered with [`TestRuntime::register`] before calling.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::botscript::host::HostApi;
+use crate::config::{
+    traits::{ProviderCall, ProviderDeserialize, ProviderSerialize},
+    Provider, RunnerConfig,
+};
+
+pub type TestFn = Arc<dyn Fn(&[Value]) -> Option<Value> + Send + Sync>;
+
+lazy_static! {
+    static ref FUNCTIONS: Mutex<HashMap<String, TestFn>> = Default::default();
+}
+
+#[derive(Clone, Default)]
+pub struct TestRuntime;
+
+impl TestRuntime {
+    pub fn register(name: &str, f: impl Fn(&[Value]) -> Option<Value> + Send + Sync + 'static) {
+        FUNCTIONS
+            .lock()
+            .expect("Poisoned test functions lock")
+            .insert(name.to_string(), Arc::new(f));
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TestError {
+    #[error("error converting value: {0:?}")]
+    Serde(#[from] serde_json::Error),
+    #[error("function `{0}` is not registered")]
+    NotRegistered(String),
+}
+
+pub struct TestInit {
+    config: String,
+}
+
+impl From<String> for TestInit {
+    fn from(config: String) -> Self {
+        Self { config }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TestValue(Value);
+
+impl ProviderDeserialize for TestValue {
+    type Provider = TestRuntime;
+
+    fn de_into<T: DeserializeOwned>(&self) -> Result<T, <Self::Provider as Provider>::Error> {
+        Ok(serde_json::from_value(self.0.clone())?)
+    }
+}
+
+impl ProviderSerialize for TestValue {
+    type Provider = TestRuntime;
+
+    fn se_from<T: Serialize>(from: &T) -> Result<Self, <Self::Provider as Provider>::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self(serde_json::to_value(from)?))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestFunction {
+    #[serde(rename = "$function")]
+    name: String,
+}
+
+#[async_trait]
+impl ProviderCall for TestFunction {
+    type Provider = TestRuntime;
+
+    async fn call_async(
+        &self,
+        args: &[&<Self::Provider as Provider>::Value],
+    ) -> Result<Option<<Self::Provider as Provider>::Value>, <Self::Provider as Provider>::Error>
+    {
+        let f = FUNCTIONS
+            .lock()
+            .expect("Poisoned test functions lock")
+            .get(&self.name)
+            .cloned()
+            .ok_or_else(|| TestError::NotRegistered(self.name.clone()))?;
+        let args: Vec<Value> = args.iter().map(|a| a.0.clone()).collect();
+
+        Ok(f(&args).map(TestValue))
+    }
+}
+
+impl Provider for TestRuntime {
+    type Function = TestFunction;
+
+    type Value = TestValue;
+
+    type Error = TestError;
+
+    type InitData = TestInit;
+
+    fn attach_host(&self, _host: HostApi) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn init_config(&self, d: Self::InitData) -> Result<RunnerConfig<Self>, Self::Error> {
+        Ok(serde_json::from_str(&d.config)?)
+    }
+}
@@ -0,0 +1,185 @@
+use std::{cell::RefCell, collections::HashMap, ffi::c_void, rc::Rc};
+
+use deno_core::JsRuntime;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use tokio::runtime::Handle;
+use v8::{Function, Global, HandleScope, Local, Object, PromiseResolver};
+
+use crate::botscript::{
+    host::{HostApi, HostFunction},
+    ScriptResult,
+};
+
+use super::{Event, V8Error, WeakEventSender};
+
+/// State of async host calls, stored in isolate's slot to be reachable from callbacks
+struct HostState {
+    handle: Handle,
+    events: WeakEventSender,
+    resolvers: HashMap<u64, Global<PromiseResolver>>,
+    next_id: u64,
+}
+
+type SharedHostState = Rc<RefCell<HostState>>;
+
+pub(super) fn attach_host(
+    runtime: &mut JsRuntime,
+    host_functions: &mut Vec<Box<HostFunction>>,
+    host: HostApi,
+    handle: Handle,
+    events: WeakEventSender,
+) -> Result<(), V8Error> {
+    let state: SharedHostState = Rc::new(RefCell::new(HostState {
+        handle,
+        events,
+        resolvers: HashMap::new(),
+        next_id: 0,
+    }));
+    runtime.v8_isolate().set_slot(state);
+
+    let scope = &mut runtime.handle_scope();
+    let context = scope.get_current_context();
+    let global = context.global(scope);
+
+    for (object, name, f) in host.entries() {
+        let f = Box::new(f.clone());
+        let data = v8::External::new(scope, &*f as *const HostFunction as *mut c_void);
+        host_functions.push(f);
+
+        let function = Function::builder(host_callback)
+            .data(data.into())
+            .build(scope)
+            .ok_or_else(|| V8Error::Other(format!("can't create host function `{name}`")))?;
+        let target = match object {
+            Some(object) => global_object(scope, global, object)?,
+            None => global,
+        };
+        let name = v8::String::new(scope, name)
+            .ok_or_else(|| V8Error::StringCreation(name.to_string()))?;
+        target.set(scope, name.into(), function.into());
+    }
+
+    Ok(())
+}
+
+/// settles promise of async host call, returned by [`host_callback`]
+pub(super) fn settle_host_call(
+    runtime: &mut JsRuntime,
+    id: u64,
+    result: Result<Value, String>,
+) -> Result<(), V8Error> {
+    let state = runtime
+        .v8_isolate()
+        .get_slot::<SharedHostState>()
+        .cloned()
+        .ok_or_else(|| V8Error::Other("host functions are not attached".to_string()))?;
+    let resolver = state
+        .borrow_mut()
+        .resolvers
+        .remove(&id)
+        .ok_or_else(|| V8Error::Other(format!("no pending host call with id {id}")))?;
+
+    let scope = &mut runtime.handle_scope();
+    let resolver = Local::new(scope, resolver);
+    match result {
+        Ok(value) => {
+            let value = serde_v8::to_v8(scope, value)?;
+            resolver.resolve(scope, value);
+        }
+        Err(err) => {
+            let exception = error_value(scope, &err);
+            resolver.reject(scope, exception);
+        }
+    }
+
+    Ok(())
+}
+
+/// object in global scope with given name, created if it is not set yet
+fn global_object<'s>(
+    scope: &mut HandleScope<'s>,
+    global: Local<'s, Object>,
+    name: &str,
+) -> Result<Local<'s, Object>, V8Error> {
+    let key =
+        v8::String::new(scope, name).ok_or_else(|| V8Error::StringCreation(name.to_string()))?;
+    if let Some(existing) = global.get(scope, key.into()) {
+        if let Ok(object) = Local::<Object>::try_from(existing) {
+            return Ok(object);
+        }
+    }
+
+    let object = Object::new(scope);
+    global.set(scope, key.into(), object.into());
+    Ok(object)
+}
+
+fn error_value<'s>(scope: &mut HandleScope<'s>, message: &str) -> Local<'s, v8::Value> {
+    let message = v8::String::new(scope, message).unwrap_or_else(|| v8::String::empty(scope));
+    v8::Exception::error(scope, message)
+}
+
+fn host_callback(
+    scope: &mut HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let data = match Local::<v8::External>::try_from(args.data()) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+    // SAFETY: pointer is set in `attach_host` to the boxed function,
+    // which is stored in runtime thread and outlives runtime
+    let f = unsafe { &*(data.value() as *const HostFunction) };
+
+    let result = (0..args.length())
+        .map(|i| serde_v8::from_v8::<Value>(scope, args.get(i)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("wrong arguments: {err}"))
+        .and_then(|args| match f {
+            HostFunction::Sync(f) => {
+                let value = f(args).map_err(|err| err.to_string())?;
+                serde_v8::to_v8(scope, value).map_err(|err| err.to_string())
+            }
+            HostFunction::Async(f) => spawn_host_call(scope, f(args)),
+        });
+
+    match result {
+        Ok(value) => rv.set(value),
+        Err(err) => {
+            let exception = error_value(scope, &err);
+            scope.throw_exception(exception);
+        }
+    }
+}
+
+/// runs host future on tokio, returning promise, that is settled by runtime thread
+/// after future is done
+fn spawn_host_call<'s>(
+    scope: &mut HandleScope<'s>,
+    future: BoxFuture<'static, ScriptResult<Value>>,
+) -> Result<Local<'s, v8::Value>, String> {
+    let state = scope
+        .get_slot::<SharedHostState>()
+        .cloned()
+        .ok_or_else(|| "host functions are not attached".to_string())?;
+    let resolver =
+        PromiseResolver::new(scope).ok_or_else(|| "failed to create promise".to_string())?;
+    let promise = resolver.get_promise(scope);
+
+    let mut state = state.borrow_mut();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.resolvers.insert(id, Global::new(scope, resolver));
+
+    let events = state.events.clone();
+    state.handle.spawn(async move {
+        let result = future.await.map_err(|err| err.to_string());
+        if let Some(events) = events.upgrade() {
+            let _ = events.send((Event::HostResolved(id, result), None));
+        }
+    });
+
+    Ok(promise.into())
+}
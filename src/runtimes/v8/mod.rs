@@ -1,86 +1,106 @@
+mod host;
 mod value_replace;
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    marker::PhantomData,
-    ops::{Deref, DerefMut},
-    sync::{
-        mpsc::{Receiver, Sender},
-        Arc, Mutex, RwLock,
-    },
+    sync::{Arc, Mutex},
     thread::JoinHandle,
 };
 
+use crate::botscript::host::{HostApi, HostFunction};
 use crate::config::{
     traits::{ProviderCall, ProviderDeserialize, ProviderSerialize},
     Provider, RunnerConfig,
 };
-use deno_core::{ascii_str, error::CoreError, FastString, JsRuntime, RuntimeOptions};
-use serde::{Deserialize, Serialize};
-use serde_v8::{from_v8, Value as SerdeValue};
-use v8::{Context, ContextScope, Function, HandleScope, Local, OwnedIsolate};
+use async_trait::async_trait;
+use deno_core::{error::CoreError, JsRuntime, RuntimeOptions};
+use log::error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    runtime::Handle,
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender, WeakUnboundedSender},
+        oneshot,
+    },
+};
+use v8::{Function, Global, HandleScope, Local, Promise, PromiseState};
 
-enum EventType {
+pub(crate) enum Event {
+    AttachHost(HostApi, Handle),
     GetScriptConfig(String),
-    ExecuteFunction(V8Function, Vec<V8Value>),
-}
-
-pub struct Event {
-    event: EventType,
-    runtime: Arc<V8Runtime>,
+    ExecuteFunction(String, Vec<Value>),
+    /// async host function is finished, promise with this id should be settled
+    HostResolved(u64, Result<Value, String>),
 }
 
-enum RuntimeReturn {
-    Value(V8Value),
-    OptionalValue(Option<V8Value>),
-    Config(RunnerConfig<V8Runtime>),
+pub(crate) enum RuntimeReturn {
+    Done,
+    OptionalValue(Option<Value>),
+    Config(Value),
+    Error(String),
 }
 
 impl RuntimeReturn {
-    fn as_value(self) -> Option<V8Value> {
-        if let Self::Value(v) = self {
-            Some(v)
-        } else {
-            None
+    fn as_done(self) -> Result<(), V8Error> {
+        match self {
+            Self::Done => Ok(()),
+            other => Err(other.into_error()),
         }
     }
 
-    fn as_optional_value(self) -> Option<Option<V8Value>> {
-        if let Self::OptionalValue(v) = self {
-            Some(v)
-        } else {
-            None
+    fn as_optional_value(self) -> Result<Option<Value>, V8Error> {
+        match self {
+            Self::OptionalValue(v) => Ok(v),
+            other => Err(other.into_error()),
         }
     }
 
-    fn as_config(self) -> Option<RunnerConfig<V8Runtime>> {
-        if let Self::Config(v) = self {
-            Some(v)
-        } else {
-            None
+    fn as_config(self) -> Result<Value, V8Error> {
+        match self {
+            Self::Config(v) => Ok(v),
+            other => Err(other.into_error()),
         }
     }
+
+    fn into_error(self) -> V8Error {
+        match self {
+            Self::Error(err) => V8Error::Execution(err),
+            _ => V8Error::Other("runtime returned unexpected type of value".to_string()),
+        }
+    }
+}
+
+type Reply = oneshot::Sender<RuntimeReturn>;
+type EventSender = UnboundedSender<(Event, Option<Reply>)>;
+type WeakEventSender = WeakUnboundedSender<(Event, Option<Reply>)>;
+
+thread_local! {
+    /// Runtime that deserialized functions are bound to.
+    /// Set only for the time of deserialization, see [`V8Runtime::with_deserialize`]
+    static DESERIALIZE_RUNTIME: RefCell<Option<V8Runtime>> = const { RefCell::new(None) };
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct V8Runtime {
     #[serde(skip, default = "default_runtime")]
-    runtime: Arc<Mutex<JoinHandle<()>>>,
+    runtime: Arc<Mutex<Option<JoinHandle<()>>>>,
     #[serde(skip, default = "default_sender")]
-    tx: Sender<Event>,
-    #[serde(skip, default = "default_receiver")]
-    rx: Arc<Mutex<Receiver<RuntimeReturn>>>,
+    tx: EventSender,
 }
 
-fn default_runtime() -> Arc<Mutex<JoinHandle<()>>> {
-    todo!()
+fn deserialize_runtime() -> V8Runtime {
+    DESERIALIZE_RUNTIME
+        .with_borrow(|runtime| runtime.clone())
+        .unwrap_or_else(V8Runtime::detached)
 }
 
-fn default_sender() -> Sender<Event> {
-    todo!()
+fn default_runtime() -> Arc<Mutex<Option<JoinHandle<()>>>> {
+    deserialize_runtime().runtime
 }
 
-fn default_receiver() -> Arc<Mutex<Receiver<RuntimeReturn>>> {
-    todo!()
+fn default_sender() -> EventSender {
+    deserialize_runtime().tx
 }
 
 impl Default for V8Runtime {
@@ -91,71 +111,211 @@ impl Default for V8Runtime {
 
 impl V8Runtime {
     pub fn new() -> Self {
-        let (tx, rx) = std::sync::mpsc::channel::<Event>();
-        let (rtx, rrx) = std::sync::mpsc::channel::<RuntimeReturn>();
-        let thread = std::thread::spawn(move || {
-            let options = RuntimeOptions::default();
-            let mut runtime = JsRuntime::new(options);
-            let handlers: HashMap<&str, v8::Local<'_, v8::Value>> = HashMap::new();
-            loop {
-                let event = match rx.recv() {
-                    Ok(event) => event,
-                    Err(err) => break,
-                };
-                match event {
-                    Event::GetScriptConfig(script) => {
-                        let code = FastString::from(script);
-
-                        let result = runtime.execute_script("", code).unwrap();
-                        let mut scope = runtime.handle_scope();
-                        let result = Local::new(&mut scope, result);
-                        value_replace::replace(&mut result, &mut runtime, &mut handlers);
-                        let config: RunnerConfig<Self> = from_v8(&mut scope, result).unwrap();
-
-                        // rtx.send(RuntimeReturn::Value(unsafe { V8Value::new(SerdeValue::from(result)) }))
-                        rtx.send(RuntimeReturn::Config(config)).unwrap();
-                    }
-                    Event::ExecuteFunction(f, args) => {
-                        let value = unsafe { f.get_inner() }.get_value();
-                        let value = handlers[value];
-                        let mut scope = runtime.handle_scope();
-                        let context = Local::new(&mut scope, runtime.main_context());
-                        let global = context.global(&mut scope).into();
-                        let f: Local<'_, Function> = value.try_into().unwrap();
-                        let args: Vec<Local<'_, v8::Value>> = args
-                            .into_iter()
-                            .map(|a| unsafe {
-                                let r = a.get_inner().get_value();
-                                r
-                            })
-                            .collect();
-                        let result = f.call(&mut scope, global, args.as_slice());
-                        let result = result.map(|r| SerdeValue::from(r));
-
-                        rtx.send(RuntimeReturn::OptionalValue(
-                            result.map(|result| unsafe { V8Value::new(SerdeValue::from(result)) }),
-                        ))
-                        .unwrap();
-                    }
-                };
-            }
-        });
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        // runtime thread keeps only weak sender to itself,
+        // so it's stopped when every handle to runtime is dropped
+        let events = tx.downgrade();
+        let thread = std::thread::spawn(move || run_runtime(rx, events));
 
         Self {
-            runtime: Arc::new(Mutex::new(thread)),
+            runtime: Arc::new(Mutex::new(Some(thread))),
             tx,
-            rx: Arc::new(Mutex::new(rrx)),
         }
     }
 
-    pub(crate) fn call_event(&self, event: Event) -> RuntimeReturn {
-        // locking before send to avoid runtime output shuffle
-        // because reciever depends on sender
-        // and runtime single-threaded anyway
-        let rx = self.rx.lock().unwrap();
-        self.tx.send(event).unwrap();
-        rx.recv().unwrap()
+    /// runtime without running thread, every call to it returns an error
+    fn detached() -> Self {
+        let (tx, _) = tokio::sync::mpsc::unbounded_channel();
+
+        Self {
+            runtime: Arc::new(Mutex::new(None)),
+            tx,
+        }
+    }
+
+    /// sends event to runtime thread and waits for its result without blocking the thread
+    pub(crate) async fn call_event(&self, event: Event) -> Result<RuntimeReturn, V8Error> {
+        let (reply, result) = oneshot::channel();
+        self.tx
+            .send((event, Some(reply)))
+            .map_err(|_| V8Error::RuntimeUnavailable("runtime thread is stopped".to_string()))?;
+
+        result
+            .await
+            .map_err(|_| V8Error::RuntimeUnavailable("runtime thread is stopped".to_string()))
+    }
+
+    /// the same as [`V8Runtime::call_event`], but blocks current thread until result,
+    /// so it's used only while script is initialized
+    pub(crate) fn call_event_blocking(&self, event: Event) -> Result<RuntimeReturn, V8Error> {
+        futures::executor::block_on(self.call_event(event))
+    }
+
+    /// deserialized inside of `f` functions will be bound to this runtime
+    fn with_deserialize<T>(&self, f: impl FnOnce() -> T) -> T {
+        let prev = DESERIALIZE_RUNTIME.replace(Some(self.clone()));
+        let result = f();
+        DESERIALIZE_RUNTIME.set(prev);
+
+        result
+    }
+}
+
+/// event loop of runtime thread
+///
+/// Functions calls are replied when returned promise is settled,
+/// so other events (including calls) are processed while async function is awaiting
+fn run_runtime(mut rx: UnboundedReceiver<(Event, Option<Reply>)>, events: WeakEventSender) {
+    // declared before runtime to outlive it, since runtime refers to them
+    let mut host_functions: Vec<Box<HostFunction>> = Vec::new();
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    let mut handlers: HashMap<String, Global<Function>> = HashMap::new();
+    let mut pending: Vec<(Global<Promise>, Reply)> = Vec::new();
+
+    while let Some((event, mut reply)) = rx.blocking_recv() {
+        let result = match event {
+            Event::AttachHost(host, handle) => host::attach_host(
+                &mut runtime,
+                &mut host_functions,
+                host,
+                handle,
+                events.clone(),
+            )
+            .map(|_| Some(RuntimeReturn::Done)),
+            Event::GetScriptConfig(script) => {
+                get_script_config(&mut runtime, &mut handlers, script)
+                    .map(|config| Some(RuntimeReturn::Config(config)))
+            }
+            Event::ExecuteFunction(f, args) => {
+                execute_function(&mut runtime, &handlers, &f, args).map(|promise| {
+                    // replied when promise is settled
+                    if let Some(reply) = reply.take() {
+                        pending.push((promise, reply));
+                    }
+                    None
+                })
+            }
+            Event::HostResolved(id, result) => {
+                host::settle_host_call(&mut runtime, id, result).map(|_| None)
+            }
+        };
+
+        match (reply, result) {
+            (Some(reply), Ok(Some(result))) => {
+                let _ = reply.send(result);
+            }
+            (Some(reply), Err(err)) => {
+                let _ = reply.send(RuntimeReturn::Error(err.to_string()));
+            }
+            (None, Err(err)) => error!("Error in v8 runtime thread: {err}"),
+            _ => {}
+        }
+
+        pending = settle_pending(&mut runtime, &mut handlers, pending);
+    }
+}
+
+/// runs microtasks and replies to calls, which promises are settled,
+/// returning calls that are still pending
+fn settle_pending(
+    runtime: &mut JsRuntime,
+    handlers: &mut HashMap<String, Global<Function>>,
+    pending: Vec<(Global<Promise>, Reply)>,
+) -> Vec<(Global<Promise>, Reply)> {
+    let scope = &mut runtime.handle_scope();
+    scope.perform_microtask_checkpoint();
+
+    let mut still_pending = Vec::new();
+    for (promise, reply) in pending {
+        let local = Local::new(scope, &promise);
+        let result = match local.state() {
+            PromiseState::Pending => {
+                still_pending.push((promise, reply));
+                continue;
+            }
+            PromiseState::Fulfilled => {
+                let value = local.result(scope);
+                optional_value(scope, value, handlers)
+                    .map(RuntimeReturn::OptionalValue)
+                    .unwrap_or_else(|err| RuntimeReturn::Error(err.to_string()))
+            }
+            PromiseState::Rejected => {
+                let exception = local.result(scope);
+                RuntimeReturn::Error(exception.to_rust_string_lossy(scope))
+            }
+        };
+        let _ = reply.send(result);
+    }
+
+    still_pending
+}
+
+fn optional_value<'s>(
+    scope: &mut HandleScope<'s>,
+    value: Local<'s, v8::Value>,
+    handlers: &mut HashMap<String, Global<Function>>,
+) -> Result<Option<Value>, V8Error> {
+    if value.is_null_or_undefined() {
+        return Ok(None);
     }
+    let value = value_replace::replace(scope, value, handlers);
+
+    Ok(Some(serde_v8::from_v8(scope, value)?))
+}
+
+fn get_script_config(
+    runtime: &mut JsRuntime,
+    handlers: &mut HashMap<String, Global<Function>>,
+    script: String,
+) -> Result<Value, V8Error> {
+    let result = runtime
+        .execute_script("script.js", script)
+        .map_err(|err| V8Error::Execution(err.to_string()))?;
+    let scope = &mut runtime.handle_scope();
+    let result = Local::new(scope, result);
+    let result = value_replace::replace(scope, result, handlers);
+
+    Ok(serde_v8::from_v8(scope, result)?)
+}
+
+/// calls function, returning promise of its result,
+/// so sync and async functions are handled in the same way
+fn execute_function(
+    runtime: &mut JsRuntime,
+    handlers: &HashMap<String, Global<Function>>,
+    f: &str,
+    args: Vec<Value>,
+) -> Result<Global<Promise>, V8Error> {
+    let f = handlers
+        .get(f)
+        .cloned()
+        .ok_or_else(|| V8Error::Other(format!("function `{f}` is not found in runtime")))?;
+    let scope = &mut runtime.handle_scope();
+    let f = Local::new(scope, f);
+    let recv = v8::undefined(scope).into();
+    let args = args
+        .iter()
+        .map(|arg| serde_v8::to_v8(scope, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let scope = &mut v8::TryCatch::new(scope);
+    let result = match f.call(scope, recv, &args) {
+        Some(result) => result,
+        None => {
+            let exception = scope
+                .exception()
+                .map(|e| e.to_rust_string_lossy(scope))
+                .unwrap_or("function call failed".to_string());
+            return Err(V8Error::Execution(exception));
+        }
+    };
+
+    let resolver = v8::PromiseResolver::new(scope)
+        .ok_or_else(|| V8Error::Other("failed to create promise".to_string()))?;
+    resolver.resolve(scope, result);
+    let promise = resolver.get_promise(scope);
+
+    Ok(Global::new(scope, promise))
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -166,6 +326,14 @@ pub enum V8Error {
     StringCreation(String),
     #[error("Deno core error: {0:?}")]
     DenoCore(#[from] CoreError),
+    #[error("error executing script: {0}")]
+    Execution(String),
+    #[error("error converting value with serde_v8: {0:?}")]
+    SerdeV8(#[from] serde_v8::Error),
+    #[error("error converting value: {0:?}")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("v8 runtime is unavailable: {0}")]
+    RuntimeUnavailable(String),
     #[error("error context: {0:?}")]
     Other(String),
 }
@@ -174,37 +342,32 @@ pub struct V8Init {
     code: String,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct V8Value {
-    value: ValueHandler,
-    runtime: Arc<V8Runtime>,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct ValueHandler {
-    value: String,
-}
-
-impl ValueHandler {
-    fn get_value(&self) -> &str {
-        &self.value
+impl From<String> for V8Init {
+    fn from(code: String) -> Self {
+        Self { code }
     }
 }
 
-impl V8Value {
-    unsafe fn get_inner(&self) -> &ValueHandler {
-        &self.value
-    }
-    unsafe fn new(runtime: Arc<V8Runtime>, value: ValueHandler) -> Self {
-        Self { runtime, value }
-    }
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct V8Value {
+    value: Value,
+    /// runtime this value came from, needed to resolve functions inside of value
+    #[serde(skip)]
+    runtime: Option<V8Runtime>,
 }
 
 impl ProviderDeserialize for V8Value {
     type Provider = V8Runtime;
 
-    fn de_into<T>(&self) -> Result<T, <Self::Provider as Provider>::Error> {
-        todo!()
+    fn de_into<T: DeserializeOwned>(&self) -> Result<T, <Self::Provider as Provider>::Error> {
+        let de = || serde_json::from_value(self.value.clone());
+        let value = match &self.runtime {
+            Some(runtime) => runtime.with_deserialize(de),
+            None => de(),
+        }?;
+
+        Ok(value)
     }
 }
 
@@ -215,54 +378,62 @@ impl ProviderSerialize for V8Value {
     where
         Self: Sized,
     {
-        todo!()
+        Ok(Self {
+            value: serde_json::to_value(from)?,
+            runtime: None,
+        })
     }
 }
 
 impl std::fmt::Debug for V8Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("V8Value")
-            .field("value", &"_".to_string())
+            .field("value", &self.value)
             .finish()
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct V8Function {
-    value: ValueHandler,
-    runtime: Arc<Mutex<V8Runtime>>,
-}
-
-impl V8Function {
-    unsafe fn get_inner(&self) -> &ValueHandler {
-        &self.value
-    }
+    #[serde(rename = "$function")]
+    key: String,
+    #[serde(skip, default = "deserialize_runtime")]
+    runtime: V8Runtime,
 }
 
+#[async_trait]
 impl ProviderCall for V8Function {
     type Provider = V8Runtime;
 
-    fn call(
+    async fn call_async(
         &self,
         args: &[&<Self::Provider as Provider>::Value],
     ) -> Result<Option<<Self::Provider as Provider>::Value>, <Self::Provider as Provider>::Error>
     {
-        let result: RuntimeReturn =
-            self.runtime
-                .lock()
-                .unwrap()
-                .call_event(Event::ExecuteFunction(
-                    self.clone(),
-                    args.into_iter().map(|v| (*v).clone()).collect(),
-                ));
-        Ok(result.as_optional_value().unwrap())
+        let args = args.iter().map(|v| v.value.clone()).collect();
+        let result = self
+            .runtime
+            .call_event(Event::ExecuteFunction(self.key.clone(), args))
+            .await?
+            .as_optional_value()?;
+
+        Ok(self.bound_value(result))
+    }
+}
+
+impl V8Function {
+    fn bound_value(&self, value: Option<Value>) -> Option<V8Value> {
+        value.map(|value| V8Value {
+            value,
+            runtime: Some(self.runtime.clone()),
+        })
     }
 }
 
 impl std::fmt::Debug for V8Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("V8Value")
-            .field("value", &"_".to_string())
+        f.debug_struct("V8Function")
+            .field("key", &self.key)
             .finish()
     }
 }
@@ -276,9 +447,126 @@ impl Provider for V8Runtime {
 
     type InitData = V8Init;
 
+    fn attach_host(&self, host: HostApi) -> Result<(), Self::Error> {
+        // async host functions are running on the runtime, that attached them
+        let handle = Handle::try_current().map_err(|err| {
+            V8Error::RuntimeUnavailable(format!("host functions require tokio runtime: {err}"))
+        })?;
+
+        self.call_event_blocking(Event::AttachHost(host, handle))?
+            .as_done()
+    }
+
     fn init_config(&self, d: Self::InitData) -> Result<RunnerConfig<Self>, Self::Error> {
-        let result = self.call_event(Event::GetScriptConfig(d.code));
-        let value = result.as_config().unwrap();
-        Ok(value)
+        let config = self
+            .call_event_blocking(Event::GetScriptConfig(d.code))?
+            .as_config()?;
+        let config = self.with_deserialize(|| serde_json::from_value(config))?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::botscript::host::arg;
+
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        function cancel_buttons() { return 'cancelation' }
+        function sum(a, b) { return a + b }
+        function throws() { throw new Error("oops") }
+
+        const dialog = {
+            commands: {
+                start: { handler: cancel_buttons },
+                sum: { handler: sum },
+                throws: { handler: throws },
+            },
+            buttons: {},
+            stateful_msg_handlers: {},
+        };
+        const c = { config: { version: 1.0 }, dialog: dialog };
+        c
+    "#;
+
+    fn init() -> RunnerConfig<V8Runtime> {
+        let runtime = V8Runtime::new();
+        runtime
+            .init_config(V8Init::from(SCRIPT.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_init_config_function() {
+        let rc = init();
+        let handler = rc.get_command_message("start").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let res = handler.call_async().await.unwrap().unwrap();
+        let sres: String = res.de_into().unwrap();
+        assert_eq!(sres, "cancelation");
+    }
+
+    #[tokio::test]
+    async fn test_function_args() {
+        let rc = init();
+        let handler = rc.get_command_message("sum").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let a = V8Value::se_from(&40).unwrap();
+        let b = V8Value::se_from(&2).unwrap();
+        let res = handler.call_args_async(&[&a, &b]).await.unwrap().unwrap();
+        let res: i64 = res.de_into().unwrap();
+        assert_eq!(res, 42);
+    }
+
+    #[tokio::test]
+    async fn test_function_exception() {
+        let rc = init();
+        let handler = rc.get_command_message("throws").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        assert!(handler.call_async().await.is_err());
+    }
+
+    #[test]
+    fn test_init_config_invalid() {
+        let runtime = V8Runtime::new();
+        let result = runtime.init_config(V8Init::from("invalid_script();".to_string()));
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_host_function() {
+        let runtime = V8Runtime::new();
+        let mut host = HostApi::new();
+        host.set_async_function("double", |args| async move {
+            let n: i64 = arg(&args, 0)?;
+            Ok(Value::from(n * 2))
+        });
+        runtime.attach_host(host).unwrap();
+
+        let script = r#"
+            async function doubled(a) { return await double(a) }
+            const c = {
+                config: { version: 1.0 },
+                dialog: { commands: { double: { handler: doubled } }, buttons: {}, stateful_msg_handlers: {} },
+            };
+            c
+        "#;
+        let rc = runtime
+            .init_config(V8Init::from(script.to_string()))
+            .unwrap();
+        let handler = rc.get_command_message("double").unwrap();
+        let handler = handler.get_handler().unwrap();
+
+        let a = V8Value::se_from(&21).unwrap();
+        let res = handler.call_args_async(&[&a]).await.unwrap().unwrap();
+        let res: i64 = res.de_into().unwrap();
+        assert_eq!(res, 42);
     }
 }
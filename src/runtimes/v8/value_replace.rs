@@ -1,13 +1,115 @@
 use std::collections::HashMap;
 
-use deno_core::JsRuntime;
-use v8::{Local, Value};
+use v8::{Array, Function, Global, HandleScope, Local, Object, Value};
+
+/// key of object, that is put in place of function
+pub(crate) const FUNCTION_KEY: &str = "$function";
+
+/// objects nested deeper are left as is, mostly to not hang on cyclic references
+const MAX_DEPTH: usize = 64;
 
 /// move out unsupported by serde types to map, leaving key instead of value to get this value
-pub(crate) fn replace(
-    value: &mut Local<'_, Value>,
-    runtime: &mut JsRuntime,
-    outmap: &mut HashMap<String, Local<'_, Value>>,
-) {
-    todo!()
+///
+/// Every function is replaced by object `{"$function": key}`, where `key` is a key in `outmap`.
+/// Arrays and plain objects are copied, so original values in script are not modified
+pub(crate) fn replace<'s>(
+    scope: &mut HandleScope<'s>,
+    value: Local<'s, Value>,
+    outmap: &mut HashMap<String, Global<Function>>,
+) -> Local<'s, Value> {
+    replace_inner(scope, value, outmap, 0)
+}
+
+fn replace_inner<'s>(
+    scope: &mut HandleScope<'s>,
+    value: Local<'s, Value>,
+    outmap: &mut HashMap<String, Global<Function>>,
+    depth: usize,
+) -> Local<'s, Value> {
+    if depth > MAX_DEPTH {
+        return value;
+    }
+
+    if let Ok(f) = Local::<Function>::try_from(value) {
+        let key = store_function(scope, f, outmap);
+        return function_placeholder(scope, &key).unwrap_or(value);
+    }
+
+    if let Ok(array) = Local::<Array>::try_from(value) {
+        let len = array.length();
+        let out = Array::new(scope, len as i32);
+        for i in 0..len {
+            let item = match array.get_index(scope, i) {
+                Some(item) => item,
+                None => continue,
+            };
+            let item = replace_inner(scope, item, outmap, depth + 1);
+            out.set_index(scope, i, item);
+        }
+        return out.into();
+    }
+
+    if is_plain_object(value) {
+        let object = match Local::<Object>::try_from(value) {
+            Ok(object) => object,
+            Err(_) => return value,
+        };
+        let names = match object.get_own_property_names(scope, Default::default()) {
+            Some(names) => names,
+            None => return value,
+        };
+        let out = Object::new(scope);
+        for i in 0..names.length() {
+            let name = match names.get_index(scope, i) {
+                Some(name) => name,
+                None => continue,
+            };
+            let item = match object.get(scope, name) {
+                Some(item) => item,
+                None => continue,
+            };
+            let item = replace_inner(scope, item, outmap, depth + 1);
+            out.set(scope, name, item);
+        }
+        return out.into();
+    }
+
+    value
+}
+
+fn is_plain_object(value: Local<'_, Value>) -> bool {
+    value.is_object()
+        && !value.is_date()
+        && !value.is_promise()
+        && !value.is_reg_exp()
+        && !value.is_array_buffer_view()
+}
+
+/// stores function in map, reusing key if the same function was already stored
+fn store_function<'s>(
+    scope: &mut HandleScope<'s>,
+    f: Local<'s, Function>,
+    outmap: &mut HashMap<String, Global<Function>>,
+) -> String {
+    let existing = outmap
+        .iter()
+        .find(|(_, stored)| Local::new(scope, *stored).strict_equals(f.into()))
+        .map(|(key, _)| key.clone());
+    if let Some(key) = existing {
+        return key;
+    }
+
+    let name = f.get_name(scope).to_rust_string_lossy(scope);
+    let key = format!("{}#{}", name, outmap.len());
+    outmap.insert(key.clone(), Global::new(scope, f));
+    key
+}
+
+fn function_placeholder<'s>(scope: &mut HandleScope<'s>, key: &str) -> Option<Local<'s, Value>> {
+    let placeholder = Object::new(scope);
+    let name = v8::String::new(scope, FUNCTION_KEY)?;
+    let key = v8::String::new(scope, key)?;
+    placeholder.set(scope, name.into(), key.into())?;
+
+    Some(placeholder.into())
 }
@@ -1,11 +1,18 @@
 pub mod parcelable;
+pub mod template;
 
 use serde::{Deserialize, Serialize};
-use teloxide::types::InlineKeyboardButton;
+use serde_json::{json, Value};
+use std::{fmt::Display, str::FromStr};
+use teloxide::types::{
+    ButtonRequest, CopyTextButton, InlineKeyboardButton, InlineKeyboardButtonKind,
+    InlineKeyboardMarkup, KeyboardButton, KeyboardMarkup, LoginUrl, ReplyMarkup, WebAppInfo,
+};
 
 use crate::{
-    db::{callback_info::CallbackInfo, CallDB},
-    BotResult,
+    config::dialog::{button::ButtonLayout, message::KeyboardType},
+    db::{callback_info::CallbackInfo, CallDB, DB},
+    BotError, BotResult,
 };
 
 #[macro_export]
@@ -83,6 +90,183 @@ where
     Ok(InlineKeyboardButton::callback(name, ci.get_id()))
 }
 
+fn parse_url<U>(url: &str) -> BotResult<U>
+where
+    U: FromStr,
+    U::Err: Display,
+{
+    url.parse()
+        .map_err(|err| BotError::BotLogicError(format!("invalid url `{url}` in button: {err}")))
+}
+
+/// Creates inline keyboard from resolved layout, storing callbacks of all
+/// its buttons with a single request
+pub struct InlineKeyboardBuilder {
+    layout: Vec<Vec<ButtonLayout>>,
+    content_ids: bool,
+}
+
+impl InlineKeyboardBuilder {
+    pub fn new(layout: Vec<Vec<ButtonLayout>>) -> Self {
+        Self {
+            layout,
+            content_ids: false,
+        }
+    }
+
+    /// reuse stored callback for buttons with the same callback and data,
+    /// see [`CallbackInfo::with_content_id`]
+    pub fn with_content_ids(self, content_ids: bool) -> Self {
+        Self {
+            content_ids,
+            ..self
+        }
+    }
+
+    fn button(
+        &self,
+        b: &ButtonLayout,
+        callbacks: &mut Vec<CallbackInfo<Value>>,
+    ) -> BotResult<InlineKeyboardButton> {
+        match b {
+            ButtonLayout::Callback {
+                name,
+                literal: _,
+                callback,
+                data,
+            } => {
+                // stored in the field, since callback data is flattened in CallbackInfo
+                let data = json!({ "data": data });
+                let ci = CallbackInfo::new_with_literal(data, callback.to_string());
+                let ci = match self.content_ids {
+                    true => ci.with_content_id()?,
+                    false => ci,
+                };
+                let button = InlineKeyboardButton::callback(name, ci.get_id());
+                callbacks.push(ci);
+                Ok(button)
+            }
+            ButtonLayout::Url { name, url } => Ok(InlineKeyboardButton::url(name, parse_url(url)?)),
+            ButtonLayout::WebApp { name, url } => Ok(InlineKeyboardButton::web_app(
+                name,
+                WebAppInfo {
+                    url: parse_url(url)?,
+                },
+            )),
+            ButtonLayout::SwitchInline {
+                name,
+                query,
+                current_chat,
+            } => Ok(match current_chat {
+                true => InlineKeyboardButton::switch_inline_query_current_chat(name, query),
+                false => InlineKeyboardButton::switch_inline_query(name, query),
+            }),
+            ButtonLayout::LoginUrl { name, url } => Ok(InlineKeyboardButton::new(
+                name,
+                InlineKeyboardButtonKind::LoginUrl(LoginUrl {
+                    url: parse_url(url)?,
+                    forward_text: None,
+                    bot_username: None,
+                    request_write_access: None,
+                }),
+            )),
+            ButtonLayout::CopyText { name, text } => Ok(InlineKeyboardButton::new(
+                name,
+                InlineKeyboardButtonKind::CopyText(CopyTextButton { text: text.clone() }),
+            )),
+            ButtonLayout::RequestContact { name }
+            | ButtonLayout::RequestLocation { name }
+            | ButtonLayout::Text { name } => Err(BotError::BotLogicError(format!(
+                "button `{name}` can be used only in reply keyboard"
+            ))),
+        }
+    }
+
+    pub async fn build(self, db: &DB) -> BotResult<InlineKeyboardMarkup> {
+        let mut callbacks = vec![];
+        let mut inline_keyboard = Vec::with_capacity(self.layout.len());
+        for r in self.layout.iter() {
+            let row = r
+                .iter()
+                .map(|b| self.button(b, &mut callbacks))
+                .collect::<Result<_, _>>()?;
+            inline_keyboard.push(row);
+        }
+        CallbackInfo::store_many(&mut db.clone(), callbacks).await?;
+
+        Ok(InlineKeyboardMarkup { inline_keyboard })
+    }
+}
+
+/// creates buttons of resolved layout, storing callbacks in db.
+/// Equal buttons share stored callback
+pub async fn inline_keyboard(
+    layout: Vec<Vec<ButtonLayout>>,
+    db: &DB,
+) -> BotResult<InlineKeyboardMarkup> {
+    InlineKeyboardBuilder::new(layout)
+        .with_content_ids(true)
+        .build(db)
+        .await
+}
+
+/// creates keyboard shown instead of user's one,
+/// callback buttons are sent by user as a plain text
+pub fn reply_keyboard(layout: Vec<Vec<ButtonLayout>>, one_time: bool) -> BotResult<KeyboardMarkup> {
+    let keyboard = layout
+        .into_iter()
+        .map(|r| {
+            r.into_iter()
+                .map(|b| match b {
+                    ButtonLayout::Callback { name, .. } | ButtonLayout::Text { name } => {
+                        Ok(KeyboardButton::new(name))
+                    }
+                    ButtonLayout::RequestContact { name } => {
+                        Ok(KeyboardButton::new(name).request(ButtonRequest::Contact))
+                    }
+                    ButtonLayout::RequestLocation { name } => {
+                        Ok(KeyboardButton::new(name).request(ButtonRequest::Location))
+                    }
+                    ButtonLayout::WebApp { name, url } => Ok(KeyboardButton::new(name).request(
+                        ButtonRequest::WebApp(WebAppInfo {
+                            url: parse_url(&url)?,
+                        }),
+                    )),
+                    ButtonLayout::Url { name, .. }
+                    | ButtonLayout::SwitchInline { name, .. }
+                    | ButtonLayout::LoginUrl { name, .. }
+                    | ButtonLayout::CopyText { name, .. } => Err(BotError::BotLogicError(format!(
+                        "button `{name}` can be used only in inline keyboard"
+                    ))),
+                })
+                .collect::<Result<_, _>>()
+        })
+        .collect::<Result<_, _>>()?;
+
+    let markup = KeyboardMarkup::new(keyboard).resize_keyboard();
+    Ok(match one_time {
+        true => markup.one_time_keyboard(),
+        false => markup,
+    })
+}
+
+/// creates markup of given type from resolved layout
+pub async fn reply_markup(
+    layout: Option<Vec<Vec<ButtonLayout>>>,
+    keyboard_type: KeyboardType,
+    db: &DB,
+) -> BotResult<Option<ReplyMarkup>> {
+    let markup = match (keyboard_type, layout) {
+        (KeyboardType::Remove, _) => Some(ReplyMarkup::kb_remove()),
+        (_, None) => None,
+        (KeyboardType::Inline, Some(layout)) => Some(inline_keyboard(layout, db).await?.into()),
+        (KeyboardType::Reply, Some(layout)) => Some(reply_keyboard(layout, false)?.into()),
+        (KeyboardType::OneTime, Some(layout)) => Some(reply_keyboard(layout, true)?.into()),
+    };
+
+    Ok(markup)
+}
+
 #[cfg(test)]
 mod tests {
 
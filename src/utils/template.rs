@@ -0,0 +1,211 @@
+//! Placeholders in literal's text, replaced before message is sent:
+//!
+//! - `{user.id}`, `{user.first_name}`, `{user.last_name}`, `{user.username}`
+//! - `{variant}` - variant of message, e.g. arg of `/start`
+//! - `{now}` or `{now:<format>}`, e.g. `{now:%d.%m}`
+//! - `{var.<name>}` - variable passed by script
+//! - `{literal:<name>}` - value of other literal, inserted as is
+//!
+//! Unknown placeholders are left as is. Values that came from user are html-escaped,
+//! since messages are sent with html parse mode
+use std::{collections::HashMap, fmt::Write};
+
+use chrono::Local;
+use serde_json::Value;
+use teloxide::utils::html;
+
+use crate::db::{CallDB, DbResult, Langs, DB};
+
+/// default format of `{now}`
+const NOW_FORMAT: &str = "%d.%m.%Y %H:%M";
+
+#[derive(Default, Clone, Debug)]
+pub struct TemplateContext {
+    pub user: Option<TemplateUser>,
+    pub variant: Option<String>,
+    pub vars: HashMap<String, String>,
+    /// languages of `{literal:<name>}` values
+    pub langs: Langs,
+}
+
+#[derive(Clone, Debug)]
+pub struct TemplateUser {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+}
+
+impl From<&teloxide::types::User> for TemplateUser {
+    fn from(user: &teloxide::types::User) -> Self {
+        Self {
+            id: user.id.0 as i64,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            username: user.username.clone(),
+        }
+    }
+}
+
+impl From<&crate::db::User> for TemplateUser {
+    fn from(user: &crate::db::User) -> Self {
+        Self {
+            id: user.id,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            username: user.username.clone(),
+        }
+    }
+}
+
+impl TemplateContext {
+    pub fn with_user(self, user: impl Into<TemplateUser>) -> Self {
+        Self {
+            user: Some(user.into()),
+            ..self
+        }
+    }
+
+    /// sets script's variables, non-string values are set as json
+    pub fn with_vars(mut self, vars: HashMap<String, Value>) -> Self {
+        let vars = vars.into_iter().map(|(name, value)| match value {
+            Value::String(s) => (name, s),
+            value => (name, value.to_string()),
+        });
+        self.vars.extend(vars);
+        self
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    /// content of `{}`, with the whole placeholder to put it back if it is unknown
+    Placeholder {
+        key: &'a str,
+        raw: &'a str,
+    },
+}
+
+fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let key_len = rest[start + 1..].find(['{', '}', '\n']);
+        match key_len.filter(|len| rest[start + 1 + len..].starts_with('}') && *len > 0) {
+            Some(len) => {
+                segments.push(Segment::Text(&rest[..start]));
+                segments.push(Segment::Placeholder {
+                    key: &rest[start + 1..start + 1 + len],
+                    raw: &rest[start..start + len + 2],
+                });
+                rest = &rest[start + len + 2..];
+            }
+            // not a placeholder, keeping brace as a text
+            None => {
+                segments.push(Segment::Text(&rest[..start + 1]));
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    segments.push(Segment::Text(rest));
+
+    segments
+}
+
+/// whether text uses placeholders of user, so it should be set in context
+pub fn uses_user(text: &str) -> bool {
+    segments(text)
+        .iter()
+        .any(|s| matches!(s, Segment::Placeholder { key, .. } if key.starts_with("user.")))
+}
+
+/// `None` if format is invalid
+fn format_now(format: &str) -> Option<String> {
+    let mut out = String::new();
+    write!(out, "{}", Local::now().format(format)).ok()?;
+    Some(out)
+}
+
+/// value of placeholder, that doesn't need db
+fn resolve(key: &str, ctx: &TemplateContext) -> Option<String> {
+    if let Some(field) = key.strip_prefix("user.") {
+        let user = ctx.user.as_ref()?;
+        let value = match field {
+            "id" => user.id.to_string(),
+            "first_name" => user.first_name.clone(),
+            "last_name" => user.last_name.clone().unwrap_or_default(),
+            "username" => user.username.clone().unwrap_or_default(),
+            _ => return None,
+        };
+        return Some(html::escape(&value));
+    }
+    if let Some(name) = key.strip_prefix("var.") {
+        return ctx.vars.get(name).map(|value| html::escape(value));
+    }
+
+    match key.split_once(':') {
+        Some(("now", format)) => format_now(format),
+        _ if key == "now" => format_now(NOW_FORMAT),
+        _ if key == "variant" => ctx.variant.as_deref().map(html::escape),
+        _ => None,
+    }
+}
+
+/// replaces placeholders in text, see module's doc
+pub async fn render(text: &str, ctx: &TemplateContext, db: &DB) -> DbResult<String> {
+    let mut out = String::with_capacity(text.len());
+    for segment in segments(text) {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Placeholder { key, raw } => {
+                let value = match key.strip_prefix("literal:") {
+                    Some(literal) => db.get_literal_value_lang(literal, &ctx.langs).await?,
+                    None => resolve(key, ctx),
+                };
+                out.push_str(value.as_deref().unwrap_or(raw));
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn render_sync(text: &str, ctx: &TemplateContext) -> String {
+        segments(text)
+            .into_iter()
+            .map(|s| match s {
+                Segment::Text(text) => text.to_string(),
+                Segment::Placeholder { key, raw } => resolve(key, ctx).unwrap_or(raw.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let ctx = TemplateContext {
+            user: Some(TemplateUser {
+                id: 1,
+                first_name: "<b>Nick</b>".to_string(),
+                last_name: None,
+                username: Some("nick".to_string()),
+            }),
+            variant: Some("promo".to_string()),
+            ..Default::default()
+        }
+        .with_vars(HashMap::from([("count".to_string(), Value::from(3))]));
+
+        let text = "Hi, {user.first_name} (@{user.username})! {variant}: {var.count} {unknown} {}";
+        assert_eq!(
+            render_sync(text, &ctx),
+            "Hi, &lt;b&gt;Nick&lt;/b&gt; (@nick)! promo: 3 {unknown} {}"
+        );
+        assert_eq!(render_sync("{ {user.id}} }", &ctx), "{ 1} }");
+        assert!(uses_user(text));
+        assert!(!uses_user("{variant}"));
+    }
+}